@@ -0,0 +1,198 @@
+// 마디·박 지도: MIDI 박자표(FF 58) 변경으로 틱이 몇 마디 몇 박인지 알려 준다.
+// 곡을 어디서 잘랐는지 사람이 읽는 위치로 알리는 데 쓴다.
+
+use serde::{Deserialize, Serialize};
+
+use crate::converter::TPB;
+
+// 박자표가 없는 곡의 박자 (4/4)
+const DEFAULT_NUMERATOR: u8 = 4;
+const DEFAULT_DENOMINATOR: u8 = 4;
+
+/// 박자표 변경 (tick 시점부터 numerator/denominator 박자, 틱 단위는 [`TPB`])
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TimeSignature {
+    pub tick: u32,
+    pub numerator: u8,
+    pub denominator: u8, // 한 박 음표 (4 = 4분음표, 8 = 8분음표)
+}
+
+impl TimeSignature {
+    /// 이 박자표가 시작한 곳에서 beats 박 뒤의 틱. 한 박은 TPB * 4 / denominator 틱이지만,
+    /// 템포 폴딩으로 틱을 1/fold 로 줄인 곡이면 박도 1/fold 로 줄어든다 (나누어떨어지지 않으면 반올림).
+    pub fn beat_tick(&self, beats: u32, fold: u32) -> u32 {
+        let ticks = beats as u64 * TPB as u64 * 4;
+        let divisor = self.denominator as u64 * fold.max(1) as u64;
+        self.tick + ((2 * ticks + divisor) / (2 * divisor)) as u32
+    }
+
+    // tick 이 들어 있는 박 (이 박자표 첫 박이 0)
+    fn beat_at(&self, tick: u32, fold: u32) -> u32 {
+        let offset = tick.saturating_sub(self.tick) as u64;
+        let divisor = TPB as u64 * 4;
+        let mut beat = (offset * self.denominator as u64 * fold.max(1) as u64 / divisor) as u32;
+        while self.beat_tick(beat + 1, fold) <= tick {
+            beat += 1;
+        }
+        while beat > 0 && self.beat_tick(beat, fold) > tick {
+            beat -= 1;
+        }
+        beat
+    }
+}
+
+// 폴딩하지 않은 곡 (serde 기본값)
+fn unfolded() -> u32 {
+    1
+}
+
+/// 곡의 마디·박 지도. 박자표가 없는 곳은 4/4 로 본다.
+/// 박자표가 마디 중간에서 바뀌면 바뀌기 전 토막도 한 마디로 센다.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BarMap {
+    pub time_signatures: Vec<TimeSignature>, // 시각순, 0번은 항상 0틱
+    #[serde(default = "unfolded")]
+    pub fold: u32, // 템포 폴딩 계수 (틱을 1/fold 로 줄였으면 박·마디도 1/fold 틱)
+}
+
+impl Default for BarMap {
+    fn default() -> Self {
+        BarMap::new(Vec::new(), 1)
+    }
+}
+
+impl BarMap {
+    /// 박자표 변경 목록과 템포 폴딩 계수로 지도를 만든다. 같은 시점에 여러 개면 마지막 것을 쓴다.
+    pub fn new(mut signatures: Vec<TimeSignature>, fold: u32) -> Self {
+        signatures.sort_by_key(|s| s.tick);
+        let mut time_signatures = vec![TimeSignature {
+            tick: 0,
+            numerator: DEFAULT_NUMERATOR,
+            denominator: DEFAULT_DENOMINATOR,
+        }];
+        for sig in signatures {
+            match time_signatures.last_mut() {
+                Some(last) if last.tick == sig.tick => *last = sig,
+                _ => time_signatures.push(sig),
+            }
+        }
+        BarMap {
+            time_signatures,
+            fold: fold.max(1),
+        }
+    }
+
+    /// tick 이 있는 마디와 그 마디 안의 박 (둘 다 1부터)
+    pub fn bar_beat(&self, tick: u32) -> (u32, u32) {
+        let mut bar = 1; // 지금 박자표 구간의 첫 마디 번호
+        let mut signatures = self.time_signatures.iter().peekable();
+        while let Some(sig) = signatures.next() {
+            let numerator = sig.numerator as u32;
+            match signatures.peek() {
+                Some(next) if next.tick <= tick => {
+                    bar += sig.beat_at(next.tick - 1, self.fold) / numerator + 1;
+                }
+                _ => {
+                    let beat = sig.beat_at(tick, self.fold);
+                    return (bar + beat / numerator, beat % numerator + 1);
+                }
+            }
+        }
+        (bar, 1)
+    }
+
+    /// from 이상 to 이하에 있는 마디 첫 틱들 (시각순). 박자표가 바뀐 곳도 새 마디의 첫 틱이다.
+    pub fn bar_lines(&self, from: u32, to: u32) -> Vec<u32> {
+        let mut lines = Vec::new();
+        for (i, sig) in self.time_signatures.iter().enumerate() {
+            let end = self.time_signatures.get(i + 1).map_or(u32::MAX, |next| next.tick);
+            if end <= from {
+                continue;
+            }
+            let numerator = sig.numerator as u32;
+            let mut bar = sig.beat_at(from.max(sig.tick), self.fold) / numerator;
+            loop {
+                let tick = sig.beat_tick(bar * numerator, self.fold);
+                if tick >= end || tick > to {
+                    break;
+                }
+                if tick >= from {
+                    lines.push(tick);
+                }
+                bar += 1;
+            }
+        }
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sig(tick: u32, numerator: u8, denominator: u8) -> TimeSignature {
+        TimeSignature {
+            tick,
+            numerator,
+            denominator,
+        }
+    }
+
+    // 박자표가 없으면 4/4: 한 마디 4박, 박마다 TPB 틱.
+    #[test]
+    fn defaults_to_four_four() {
+        let map = BarMap::default();
+        assert_eq!(map.bar_beat(0), (1, 1));
+        assert_eq!(map.bar_beat(TPB * 3), (1, 4));
+        assert_eq!(map.bar_beat(TPB * 4), (2, 1));
+        assert_eq!(map.bar_beat(TPB * 4 * 10 + TPB + 10), (11, 2));
+    }
+
+    // 3/4 두 마디 뒤 6/8: 8분음표가 한 박이고, 박자가 바뀐 곳부터 새 마디를 센다.
+    #[test]
+    fn time_signature_changes_restart_bar_counting() {
+        let map = BarMap::new(vec![sig(TPB * 6, 6, 8), sig(0, 3, 4)], 1);
+        assert_eq!(map.time_signatures.len(), 2);
+        assert_eq!(map.bar_beat(TPB * 3), (2, 1));
+        assert_eq!(map.bar_beat(TPB * 6), (3, 1));
+        assert_eq!(map.bar_beat(TPB * 6 + TPB / 2 * 5), (3, 6));
+        assert_eq!(map.bar_beat(TPB * 9), (4, 1));
+    }
+
+    // 마디 중간에서 박자가 바뀌면 앞 토막도 한 마디다.
+    #[test]
+    fn partial_bar_before_change_counts_as_bar() {
+        let map = BarMap::new(vec![sig(TPB * 6, 3, 4)], 1);
+        assert_eq!(map.bar_beat(TPB * 5), (2, 2));
+        assert_eq!(map.bar_beat(TPB * 6), (3, 1));
+    }
+
+    // 템포를 접은 곡(fold 2)은 틱이 반으로 줄었으므로 4/4 한 마디가 TPB * 2 틱이다.
+    // fold 3 처럼 나누어떨어지지 않는 박(4분음표 = TPB / 3 틱)도 반올림한 곳에서 다음 박이 된다.
+    #[test]
+    fn folded_tempo_shortens_bars() {
+        let map = BarMap::new(vec![sig(TPB * 4, 3, 4)], 2);
+        assert_eq!(map.bar_beat(TPB * 2), (2, 1));
+        assert_eq!(map.bar_beat(TPB * 4 - TPB / 2), (2, 4));
+        assert_eq!(map.bar_beat(TPB * 4), (3, 1));
+        assert_eq!(map.bar_beat(TPB * 4 + TPB / 2 * 3), (4, 1));
+
+        let map = BarMap::new(Vec::new(), 5);
+        let beat = sig(0, 4, 4).beat_tick(1, 5);
+        assert_eq!(beat, 77); // 384 / 5 = 76.8
+        assert_eq!(map.bar_beat(beat - 1), (1, 1));
+        assert_eq!(map.bar_beat(beat), (1, 2));
+        assert_eq!(map.bar_beat(sig(0, 4, 4).beat_tick(4, 5)), (2, 1));
+    }
+
+    // 마디선은 박자표 구간마다 그 박자의 마디 길이로 놓이고, 마디 중간에서 바뀐 곳도 마디선이다.
+    #[test]
+    fn bar_lines_follow_time_signatures() {
+        let map = BarMap::new(vec![sig(0, 3, 4), sig(TPB * 7, 6, 8)], 1);
+        assert_eq!(map.bar_lines(0, TPB * 10), vec![0, TPB * 3, TPB * 6, TPB * 7, TPB * 10]);
+        assert_eq!(map.bar_lines(1, TPB * 7 - 1), vec![TPB * 3, TPB * 6]);
+
+        let folded = BarMap::new(Vec::new(), 2);
+        assert_eq!(folded.bar_lines(1, TPB * 4), vec![TPB * 2, TPB * 4]);
+    }
+}
@@ -0,0 +1,610 @@
+// 딸깍악보 명령줄 도구: 창 없이 여러 MIDI 파일을 한꺼번에 MML 로 변환한다.
+//
+//   m2mm [옵션] <파일.mid | 폴더>...
+//
+// 폴더를 주면 그 안의 .mid/.midi 파일을 모두 변환한다(하위 폴더는 보지 않음).
+// 결과는 파일마다 <이름>.mml.txt 또는 <이름>.mml.json 으로 저장한다. --wav 면 <이름>.wav 도.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use m2mm_core::{
+    convert, encode_wav, render_samples, ConversionError, ConversionOptions, ConversionResult,
+    MmlParseError, OctaveRange, PercussionPitches, PitchBendMode, PreviewVoice, Transpose,
+    VoiceOctaveRange, DEFAULT_CHAR_LIMIT,
+};
+
+const USAGE: &str = "\
+사용법: m2mm [옵션] <파일.mid | 폴더>...
+
+옵션:
+  -m, --mode <solo|duo|ensemble>  연주 방식 (기본 solo)
+  -l, --limit <글자수>            악보 한 칸 글자 수 한도 (기본 2400)
+  -f, --format <txt|json>         출력 형식 (기본 txt)
+  -o, --out <폴더>                출력 폴더 (기본: 입력 파일과 같은 폴더)
+      --split                     한도를 넘는 곡을 잘라내지 않고 여러 칸으로 나눔
+      --balance                   자르기 전에 글자를 많이 쓰는 파트부터 단순화 (꾸밈음·반복음·박자 격자)
+      --simplify                  자르기 전에 모든 파트를 단계별로 단순화 (박자 격자 → 짧은 음 →
+                                  반복음 → 가운데 화음 빼기, 곡이 다 들어갈 때까지)
+      --sostenuto                 소스테누토 페달(CC66) 반영
+      --pitch-bend <sustain|steps> 피치 벤드 반영 (sustain: 가장 오래 머문 음높이로,
+                                  steps: 반음을 넘는 벤드를 반음 계단으로 나눔)
+      --drums                     드럼 파트(10번 채널·GS/XG 드럼 세트)를 버리지 않고 리듬 파트 하나로 만듦
+      --drum-pitches <킥,스네어,하이햇>  리듬 파트에서 칠 MIDI 음높이 (기본 48,55,60, --drums 포함)
+      --dynamics <단계>           셈여림을 V 명령으로 반영 (V 변화 히스테리시스)
+      --tracks <번호,...>         이 트랙만 변환 (1부터, 예: --tracks 2,3)
+      --exclude-tracks <번호,...> 이 트랙은 빼고 변환
+      --channels <번호,...>       이 채널만 변환 (1~16)
+      --exclude-channels <번호,...> 이 채널은 빼고 변환
+      --melody-track <번호>       이 트랙을 멜로디로 고정 (다른 음과 섞지 않고 첫 파트에)
+      --melody-channel <번호>     이 채널을 멜로디로 고정 (1~16)
+  -t, --transpose <반음|auto>     조옮김 (auto: 옥타브 범위 밖 음·옥타브 이동이 적어지게 자동)
+      --octave-range <파트=최소-최대>  파트 옥타브 범위, 밖의 음은 옥타브를 접어 넣음 (여러 번 가능,
+                                  예: --octave-range 베이스=1-4, 파트는 번호를 뗀 이름)
+      --wav                       미리 듣기 WAV(<이름>.wav)도 저장 (여러 칸이면 이어 붙임)
+  -h, --help                      도움말
+
+종료 코드:
+  0 성공, 1 사용법·입출력 오류, 2 MIDI 해석 오류, 3 음이 없는 곡(--drums 없이 드럼만 있는 곡 포함), 4 글자수 한도로 잘림,
+  5 글자수 한도를 넘긴 칸이 있음(--split 으로 나눠도 그리드 한 칸조차 한도에 들지 않는 곡)
+  (여러 파일이면 가장 심각한 결과)";
+
+// 파일 하나의 변환 결과. 뒤로 갈수록 심각하며, 여러 파일이면 가장 심각한 것을 종료 코드로 쓴다.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Outcome {
+    Ok,
+    Truncated,
+    OverLimit, // 게임에 붙여넣을 수 없는 칸이 있어 잘린 것보다 심각하다
+    Empty,
+    ParseError,
+    IoError,
+}
+
+impl Outcome {
+    fn exit_code(self) -> u8 {
+        match self {
+            Outcome::Ok => 0,
+            Outcome::IoError => 1,
+            Outcome::ParseError => 2,
+            Outcome::Empty => 3,
+            Outcome::Truncated => 4,
+            Outcome::OverLimit => 5,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum OutputFormat {
+    Text,
+    Json,
+}
+
+impl OutputFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Text => "mml.txt",
+            OutputFormat::Json => "mml.json",
+        }
+    }
+}
+
+#[derive(Debug)]
+struct CliArgs {
+    inputs: Vec<PathBuf>,
+    out_dir: Option<PathBuf>,
+    format: OutputFormat,
+    wav: bool,
+    options: ConversionOptions,
+}
+
+// 명령줄 인자 해석. --help 면 Ok(None).
+fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Option<CliArgs>, String> {
+    let mut cli = CliArgs {
+        inputs: Vec::new(),
+        out_dir: None,
+        format: OutputFormat::Text,
+        wav: false,
+        options: ConversionOptions {
+            char_limit: DEFAULT_CHAR_LIMIT,
+            mode: "solo".to_string(),
+            split_pages: false,
+            sostenuto_pedal: false,
+            dynamics: None,
+            source: Default::default(),
+            melody_track: None,
+            melody_channel: None,
+            transpose: Transpose::default(),
+            octave_ranges: Vec::new(),
+            balance_voices: false,
+            auto_simplify: false,
+            pitch_bend: PitchBendMode::default(),
+            percussion: None,
+            key: None,
+        },
+    };
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| next_value(&mut args, name);
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(None),
+            "-m" | "--mode" => {
+                let mode = value(&arg)?;
+                if !matches!(mode.as_str(), "solo" | "duo" | "ensemble") {
+                    return Err(format!("알 수 없는 모드: {} (solo/duo/ensemble)", mode));
+                }
+                cli.options.mode = mode;
+            }
+            "-l" | "--limit" => {
+                let limit = value(&arg)?;
+                cli.options.char_limit = limit
+                    .parse()
+                    .ok()
+                    .filter(|&n| n > 0)
+                    .ok_or_else(|| format!("글자 수 한도가 올바르지 않습니다: {}", limit))?;
+            }
+            "-f" | "--format" => {
+                cli.format = match value(&arg)?.as_str() {
+                    "txt" => OutputFormat::Text,
+                    "json" => OutputFormat::Json,
+                    other => return Err(format!("알 수 없는 출력 형식: {} (txt/json)", other)),
+                };
+            }
+            "-o" | "--out" => cli.out_dir = Some(PathBuf::from(value(&arg)?)),
+            "--split" => cli.options.split_pages = true,
+            "--balance" => cli.options.balance_voices = true,
+            "--simplify" => cli.options.auto_simplify = true,
+            "--wav" => cli.wav = true,
+            "--sostenuto" => cli.options.sostenuto_pedal = true,
+            "--pitch-bend" => {
+                cli.options.pitch_bend = match value(&arg)?.as_str() {
+                    "ignore" => PitchBendMode::Ignore,
+                    "sustain" => PitchBendMode::Sustain,
+                    "steps" => PitchBendMode::Steps,
+                    other => {
+                        return Err(format!("알 수 없는 피치 벤드 방식: {} (sustain/steps)", other));
+                    }
+                };
+            }
+            "--drums" => {
+                cli.options.percussion.get_or_insert_with(PercussionPitches::default);
+            }
+            "--drum-pitches" => cli.options.percussion = Some(drum_pitches(&value(&arg)?)?),
+            "--dynamics" => {
+                let steps = value(&arg)?;
+                let steps = steps
+                    .parse()
+                    .map_err(|_| format!("셈여림 단계가 올바르지 않습니다: {}", steps))?;
+                cli.options.dynamics = Some(steps);
+            }
+            "--tracks" => cli.options.source.include_tracks = track_list(&value(&arg)?)?,
+            "--exclude-tracks" => cli.options.source.exclude_tracks = track_list(&value(&arg)?)?,
+            "--channels" => cli.options.source.include_channels = channel_list(&value(&arg)?)?,
+            "--exclude-channels" => {
+                cli.options.source.exclude_channels = channel_list(&value(&arg)?)?;
+            }
+            "-t" | "--transpose" => {
+                let transpose = value(&arg)?;
+                cli.options.transpose = match transpose.as_str() {
+                    "auto" => Transpose::Auto,
+                    semitones => Transpose::Semitones(
+                        semitones
+                            .parse()
+                            .map_err(|_| format!("조옮김 값이 올바르지 않습니다: {}", transpose))?,
+                    ),
+                };
+            }
+            "--octave-range" => cli.options.octave_ranges.push(octave_range(&value(&arg)?)?),
+            "--melody-track" => cli.options.melody_track = single(track_list(&value(&arg)?)?)?,
+            "--melody-channel" => {
+                cli.options.melody_channel = single(channel_list(&value(&arg)?)?)?;
+            }
+            _ if arg.starts_with('-') && arg.len() > 1 => {
+                return Err(format!("알 수 없는 옵션: {}", arg));
+            }
+            _ => cli.inputs.push(PathBuf::from(arg)),
+        }
+    }
+
+    if cli.inputs.is_empty() {
+        return Err("변환할 MIDI 파일이나 폴더를 지정하세요".to_string());
+    }
+    Ok(Some(cli))
+}
+
+// 쉼표로 구분한 1부터의 번호 목록 → 0부터의 번호 (max 이하만 허용)
+fn number_list(value: &str, max: usize) -> Result<Vec<usize>, String> {
+    value
+        .split(',')
+        .map(|item| match item.trim().parse::<usize>() {
+            Ok(n) if (1..=max).contains(&n) => Ok(n - 1),
+            _ => Err(format!("번호가 올바르지 않습니다: {} (1~{})", item, max)),
+        })
+        .collect()
+}
+
+fn track_list(value: &str) -> Result<Vec<usize>, String> {
+    number_list(value, u16::MAX as usize)
+}
+
+fn channel_list(value: &str) -> Result<Vec<u8>, String> {
+    Ok(number_list(value, 16)?.into_iter().map(|c| c as u8).collect())
+}
+
+// "베이스=1-4" → 베이스 보이스 O1~O4
+fn octave_range(value: &str) -> Result<VoiceOctaveRange, String> {
+    let invalid = || format!("옥타브 범위가 올바르지 않습니다: {} (예: 베이스=1-4)", value);
+    let (voice, range) = value.split_once('=').ok_or_else(invalid)?;
+    let (min, max) = range.split_once('-').ok_or_else(invalid)?;
+    let (Ok(min), Ok(max)) = (min.trim().parse(), max.trim().parse()) else {
+        return Err(invalid());
+    };
+    if voice.trim().is_empty() || !(1..=8).contains(&min) || !(min..=8).contains(&max) {
+        return Err(invalid());
+    }
+    Ok(VoiceOctaveRange {
+        voice: voice.trim().to_string(),
+        range: OctaveRange { min, max },
+    })
+}
+
+// "48,55,60" → 킥·스네어·하이햇 음높이
+fn drum_pitches(value: &str) -> Result<PercussionPitches, String> {
+    let pitches: Vec<u8> = value
+        .split(',')
+        .map(|item| item.trim().parse::<u8>().ok().filter(|&p| p <= 127))
+        .collect::<Option<_>>()
+        .ok_or_else(|| format!("드럼 음높이가 올바르지 않습니다: {} (예: 48,55,60)", value))?;
+    match pitches[..] {
+        [kick, snare, hat] => Ok(PercussionPitches { kick, snare, hat }),
+        _ => Err(format!("드럼 음높이는 킥,스네어,하이햇 세 개입니다: {}", value)),
+    }
+}
+
+// 번호 하나만 받는 옵션
+fn single<T>(mut list: Vec<T>) -> Result<Option<T>, String> {
+    match list.len() {
+        1 => Ok(list.pop()),
+        _ => Err("번호를 하나만 지정하세요".to_string()),
+    }
+}
+
+fn next_value(args: &mut impl Iterator<Item = String>, name: &str) -> Result<String, String> {
+    args.next()
+        .ok_or_else(|| format!("{} 옵션에 값이 필요합니다", name))
+}
+
+fn is_midi_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| e.eq_ignore_ascii_case("mid") || e.eq_ignore_ascii_case("midi"))
+}
+
+// 입력 목록을 변환할 파일 목록으로 펼친다. 폴더는 안의 MIDI 파일을 이름순으로.
+fn collect_midi_files(inputs: &[PathBuf]) -> Result<Vec<PathBuf>, String> {
+    let mut files = Vec::new();
+    for input in inputs {
+        if input.is_dir() {
+            let entries = fs::read_dir(input)
+                .map_err(|e| format!("{}: 폴더를 읽을 수 없습니다: {}", input.display(), e))?;
+            let mut found: Vec<PathBuf> = entries
+                .filter_map(|e| e.ok().map(|e| e.path()))
+                .filter(|p| p.is_file() && is_midi_file(p))
+                .collect();
+            found.sort();
+            files.extend(found);
+        } else {
+            files.push(input.clone());
+        }
+    }
+    Ok(files)
+}
+
+// 사람이 읽고 바로 복사해 쓰는 텍스트 형식: 칸·파트마다 제목 줄 + MML 한 줄
+fn render_text(result: &ConversionResult) -> String {
+    let mut out = String::new();
+    let mut notes = Vec::new();
+    if result.transpose != 0 {
+        notes.push(format!("조옮김 {:+}반음", result.transpose));
+    }
+    if !result.simplifications.is_empty() {
+        let levels: Vec<&str> = result.simplifications.iter().map(|l| l.label()).collect();
+        notes.push(format!("단순화 {}", levels.join(", ")));
+    }
+    if !result.cuts.is_empty() {
+        let cuts: Vec<String> = result
+            .cuts
+            .iter()
+            .map(|c| {
+                let secs = c.seconds.round() as u32;
+                format!("{}마디 {}박 ({}:{:02})", c.bar, c.beat, secs / 60, secs % 60)
+            })
+            .collect();
+        let label = if result.pages.len() > 1 { "칸 경계" } else { "자른 곳" };
+        notes.push(format!("{} {}", label, cuts.join(", ")));
+    }
+    if !notes.is_empty() {
+        out.push_str(&notes.join("\n"));
+        out.push_str("\n\n");
+    }
+    let paged = result.pages.len() > 1;
+    for (i, page) in result.pages.iter().enumerate() {
+        for voice in page {
+            let title = if paged {
+                format!("{}부 {}", i + 1, voice.name)
+            } else {
+                voice.name.clone()
+            };
+            let over = if voice.over_limit { " (한도 초과)" } else { "" };
+            out.push_str(&format!(
+                "[{}] {}자{}, {}음\n",
+                title, voice.char_count, over, voice.note_count
+            ));
+            out.push_str(&voice.content);
+            out.push_str("\n\n");
+        }
+    }
+    out
+}
+
+// 칸마다 모든 파트를 섞어 합성하고, 칸들을 차례로 이어 붙인 미리 듣기 WAV
+fn render_preview(result: &ConversionResult) -> Result<Vec<u8>, MmlParseError> {
+    let mut samples = Vec::new();
+    for page in &result.pages {
+        let voices: Vec<PreviewVoice> = page
+            .iter()
+            .map(|v| PreviewVoice {
+                mml: v.content.clone(),
+                program: v.program,
+            })
+            .collect();
+        samples.extend(render_samples(&voices)?);
+    }
+    Ok(encode_wav(&samples))
+}
+
+fn output_path(input: &Path, out_dir: Option<&Path>, ext: &str) -> PathBuf {
+    let stem = input.file_stem().unwrap_or_default().to_string_lossy();
+    let dir = out_dir.or_else(|| input.parent()).unwrap_or(Path::new("."));
+    dir.join(format!("{}.{}", stem, ext))
+}
+
+// 파일 하나를 변환해 저장하고 결과를 알린다.
+fn convert_file(path: &Path, cli: &CliArgs) -> Outcome {
+    let midi_data = match fs::read(path) {
+        Ok(data) => data,
+        Err(e) => {
+            eprintln!("{}: 파일을 읽을 수 없습니다: {}", path.display(), e);
+            return Outcome::IoError;
+        }
+    };
+    let result = match convert(&midi_data, &cli.options) {
+        Ok(result) => result,
+        Err(e) => {
+            eprintln!("{}: [{}] {}", path.display(), e.code(), e);
+            return match e {
+                ConversionError::NoNotes | ConversionError::DrumOnly { .. } => Outcome::Empty,
+                _ => Outcome::ParseError,
+            };
+        }
+    };
+
+    let contents = match cli.format {
+        OutputFormat::Text => render_text(&result),
+        OutputFormat::Json => match serde_json::to_string_pretty(&result) {
+            Ok(json) => json,
+            Err(e) => {
+                eprintln!("{}: JSON 변환 실패: {}", path.display(), e);
+                return Outcome::IoError;
+            }
+        },
+    };
+    let out_path = output_path(path, cli.out_dir.as_deref(), cli.format.extension());
+    if let Err(e) = fs::write(&out_path, contents) {
+        eprintln!("{}: 저장 실패: {}", out_path.display(), e);
+        return Outcome::IoError;
+    }
+    println!("{} → {}", path.display(), out_path.display());
+
+    if cli.wav {
+        let wav_path = output_path(path, cli.out_dir.as_deref(), "wav");
+        let saved = render_preview(&result)
+            .map_err(|e| e.to_string())
+            .and_then(|wav| fs::write(&wav_path, wav).map_err(|e| e.to_string()));
+        if let Err(e) = saved {
+            eprintln!("{}: 미리 듣기 저장 실패: {}", wav_path.display(), e);
+            return Outcome::IoError;
+        }
+        println!("{} → {}", path.display(), wav_path.display());
+    }
+
+    let over_limit = result.over_limit_pages();
+    if !over_limit.is_empty() {
+        let pages: Vec<String> = over_limit.iter().map(|i| format!("{}부", i + 1)).collect();
+        eprintln!(
+            "{}: 너무 빽빽해 글자 수 한도({}자)를 넘긴 칸이 있습니다: {}",
+            path.display(),
+            cli.options.char_limit,
+            pages.join(", ")
+        );
+    }
+    if result.is_truncated() {
+        eprintln!(
+            "{}: 글자 수 한도({}자)를 넘어 뒷부분이 잘렸습니다 (--split 으로 여러 칸에 나눌 수 있음)",
+            path.display(),
+            cli.options.char_limit
+        );
+    }
+    limit_outcome(&result)
+}
+
+// 글자 수 한도에 걸린 결과: 넘친 칸이 있으면 OverLimit, 뒷부분이 잘렸으면 Truncated
+fn limit_outcome(result: &ConversionResult) -> Outcome {
+    if !result.over_limit_pages().is_empty() {
+        Outcome::OverLimit
+    } else if result.is_truncated() {
+        Outcome::Truncated
+    } else {
+        Outcome::Ok
+    }
+}
+
+fn main() -> ExitCode {
+    let cli = match parse_args(std::env::args().skip(1)) {
+        Ok(Some(cli)) => cli,
+        Ok(None) => {
+            println!("{}", USAGE);
+            return ExitCode::SUCCESS;
+        }
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            return ExitCode::from(Outcome::IoError.exit_code());
+        }
+    };
+
+    let files = match collect_midi_files(&cli.inputs) {
+        Ok(files) => files,
+        Err(e) => {
+            eprintln!("{}", e);
+            return ExitCode::from(Outcome::IoError.exit_code());
+        }
+    };
+    if let Some(dir) = &cli.out_dir
+        && let Err(e) = fs::create_dir_all(dir)
+    {
+        eprintln!("{}: 출력 폴더를 만들 수 없습니다: {}", dir.display(), e);
+        return ExitCode::from(Outcome::IoError.exit_code());
+    }
+
+    let worst = files
+        .iter()
+        .map(|path| convert_file(path, &cli))
+        .max()
+        .unwrap_or(Outcome::Ok);
+    ExitCode::from(worst.exit_code())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use m2mm_core::{VoiceFidelity, VoiceResult};
+
+    fn args(list: &[&str]) -> Result<Option<CliArgs>, String> {
+        parse_args(list.iter().map(|s| s.to_string()))
+    }
+
+    #[test]
+    fn parses_options_and_inputs() {
+        let cli = args(&["-m", "duo", "-l", "1200", "-f", "json", "--split", "--wav", "a.mid", "songs"])
+            .unwrap()
+            .unwrap();
+        assert_eq!(cli.options.mode, "duo");
+        assert_eq!(cli.options.char_limit, 1200);
+        assert!(cli.options.split_pages);
+        assert_eq!(cli.format, OutputFormat::Json);
+        assert!(cli.wav);
+        assert_eq!(cli.inputs, vec![PathBuf::from("a.mid"), PathBuf::from("songs")]);
+        assert!(!cli.options.balance_voices);
+        assert!(args(&["--balance", "a.mid"]).unwrap().unwrap().options.balance_voices);
+        assert!(args(&["--simplify", "a.mid"]).unwrap().unwrap().options.auto_simplify);
+    }
+
+    #[test]
+    fn rejects_bad_arguments() {
+        assert!(args(&[]).is_err(), "입력이 없으면 오류");
+        assert!(args(&["-m", "trio", "a.mid"]).is_err());
+        assert!(args(&["-l", "0", "a.mid"]).is_err());
+        assert!(args(&["a.mid", "--limit"]).is_err(), "값이 빠진 옵션");
+        assert!(args(&["--help"]).unwrap().is_none());
+        assert!(args(&["--channels", "17", "a.mid"]).is_err(), "채널은 1~16");
+        assert!(args(&["--tracks", "0", "a.mid"]).is_err(), "트랙은 1부터");
+    }
+
+    // 트랙·채널 번호는 사람이 보는 1부터로 받아 0부터로 넘긴다.
+    #[test]
+    fn track_and_channel_lists_are_zero_based() {
+        let cli = args(&["--tracks", "2,3", "--exclude-channels", "10", "a.mid"])
+            .unwrap()
+            .unwrap();
+        assert_eq!(cli.options.source.include_tracks, vec![1, 2]);
+        assert_eq!(cli.options.source.exclude_channels, vec![9]);
+
+        let cli = args(&["--melody-track", "4", "a.mid"]).unwrap().unwrap();
+        assert_eq!(cli.options.melody_track, Some(3));
+        assert!(args(&["--melody-channel", "1,2", "a.mid"]).is_err(), "멜로디는 하나만");
+
+        let cli = args(&["-t", "-3", "a.mid"]).unwrap().unwrap();
+        assert_eq!(cli.options.transpose, Transpose::Semitones(-3));
+        let cli = args(&["--transpose", "auto", "a.mid"]).unwrap().unwrap();
+        assert_eq!(cli.options.transpose, Transpose::Auto);
+        assert!(args(&["-t", "up", "a.mid"]).is_err());
+
+        let cli = args(&["--pitch-bend", "steps", "a.mid"]).unwrap().unwrap();
+        assert_eq!(cli.options.pitch_bend, PitchBendMode::Steps);
+        assert!(args(&["--pitch-bend", "bend", "a.mid"]).is_err());
+
+        assert_eq!(args(&["a.mid"]).unwrap().unwrap().options.percussion, None);
+        let cli = args(&["--drums", "a.mid"]).unwrap().unwrap();
+        assert_eq!(cli.options.percussion, Some(PercussionPitches::default()));
+        let cli = args(&["--drum-pitches", "36,38,42", "--drums", "a.mid"]).unwrap().unwrap();
+        assert_eq!(cli.options.percussion, Some(PercussionPitches { kick: 36, snare: 38, hat: 42 }));
+        assert!(args(&["--drum-pitches", "36,38", "a.mid"]).is_err());
+        assert!(args(&["--drum-pitches", "36,38,128", "a.mid"]).is_err());
+
+        let cli = args(&["--octave-range", "베이스=1-4", "a.mid"]).unwrap().unwrap();
+        assert_eq!(cli.options.octave_ranges[0].voice, "베이스");
+        assert_eq!(cli.options.octave_ranges[0].range, OctaveRange { min: 1, max: 4 });
+        assert!(args(&["--octave-range", "베이스=4-1", "a.mid"]).is_err());
+        assert!(args(&["--octave-range", "베이스", "a.mid"]).is_err());
+    }
+
+    // 여러 파일이면 가장 심각한 결과가 종료 코드가 된다.
+    #[test]
+    fn worst_outcome_wins() {
+        let worst = [Outcome::Truncated, Outcome::ParseError, Outcome::Ok].into_iter().max();
+        assert_eq!(worst.map(Outcome::exit_code), Some(2));
+        assert_eq!(Outcome::Empty.max(Outcome::Truncated).exit_code(), 3);
+        assert_eq!(Outcome::OverLimit.max(Outcome::Truncated).exit_code(), 5);
+    }
+
+    // 칸 나누기로도 한도를 넘긴 칸이 남으면 성공이 아니라 종료 코드 5 로 알린다.
+    #[test]
+    fn over_limit_pages_are_not_success() {
+        let voice = |char_count: usize, over_limit: bool| VoiceResult {
+            name: "멜로디".to_string(),
+            content: String::new(),
+            char_count,
+            note_count: 1,
+            duration: 0.0,
+            program: 0,
+            fidelity: VoiceFidelity::default(),
+            over_limit,
+        };
+        let result = |pages: Vec<Vec<VoiceResult>>| ConversionResult {
+            success: true,
+            total_notes: pages.iter().flatten().map(|v| v.note_count).sum(),
+            pages,
+            ..ConversionResult::failure(ConversionError::NoNotes)
+        };
+        assert_eq!(limit_outcome(&result(vec![vec![voice(900, false)]])), Outcome::Ok);
+        let over = result(vec![vec![voice(900, false)], vec![voice(1300, true)]]);
+        assert_eq!(limit_outcome(&over).exit_code(), 5);
+        let mut cropped = result(vec![vec![voice(900, false)]]);
+        cropped.total_notes += 1;
+        assert_eq!(limit_outcome(&cropped), Outcome::Truncated);
+    }
+
+    #[test]
+    fn output_goes_next_to_input_by_default() {
+        let input = Path::new("songs/봄.mid");
+        assert_eq!(
+            output_path(input, None, OutputFormat::Text.extension()),
+            PathBuf::from("songs/봄.mml.txt")
+        );
+        assert_eq!(
+            output_path(input, Some(Path::new("out")), OutputFormat::Json.extension()),
+            PathBuf::from("out/봄.mml.json")
+        );
+    }
+}
@@ -0,0 +1,1841 @@
+// 변환 파이프라인: MIDI 바이트 → 보이스 분배 → 글자수 한도에 맞춘 칸별 MML.
+// 데스크톱 앱(Tauri 명령)과 CLI 가 같은 경로로 변환하도록 여기에 모은다.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use crate::bar_map::BarMap;
+use crate::converter::{
+    allocate_voices_by_instrument, allocate_voices_capped, extract_midi_notes, generate_mml_final,
+    generate_mml_with_offsets, fold_octaves, mml_octave, split_bass_line, split_melody_line,
+    transpose_notes, ExtractOptions, ExtractedMidi, MmlOptions, Note, OctaveRange, PitchBendMode,
+    SourceFilter, TempoChange, GRID_SIZE, TPB,
+};
+use crate::error::ConversionError;
+use crate::key::Key;
+use crate::mml_parser::parse_mml;
+use crate::percussion::{rhythm_voice, PercussionPitches};
+use crate::simplify::{simplify_voice, SimplifyLevel, BALANCE_STEPS, SIMPLIFY_LADDER};
+use crate::utils::mml::gm_family_name;
+
+// 화음(합주) 모드 최대 보이스 수
+const MAX_VOICES: usize = 6;
+// 단독(혼자) 모드 보이스 수
+const SOLO_VOICES: usize = 3;
+// 2인 모드 보이스 수 (앞 3개 + 베이스 1개)
+const DUO_VOICES: usize = 4;
+// 리듬 파트 보이스 이름 (음역 설정도 이 이름으로 찾는다)
+const RHYTHM_NAME: &str = "드럼";
+
+/// 악보 1칸 글자 수 기본값(현재 게임 한도). 게임사가 늘리면 이 값만 바꾸면 됨 (1200 → 2400 전례).
+/// 프론트엔드 App.svelte 의 `MML_CHAR_LIMIT` 와 같은 값으로 유지한다.
+pub const DEFAULT_CHAR_LIMIT: usize = 2400;
+
+// 틱을 실제 시간(초)으로 변환
+fn ticks_to_seconds(ticks: u32, bpm: u32) -> f64 {
+    // ticks / TPB = quarter notes (박자 수)
+    // quarter notes / BPM * 60 = 초
+    let quarter_notes = ticks as f64 / TPB as f64;
+    quarter_notes / bpm as f64 * 60.0
+}
+
+// 템포 변경을 따라 곡 처음부터 tick 까지 걸리는 시간(초)
+fn seconds_at(tick: u32, bpm: u32, tempo_changes: &[TempoChange]) -> f64 {
+    let (mut seconds, mut from, mut current) = (0.0, 0, bpm);
+    for change in tempo_changes.iter().take_while(|t| t.tick < tick) {
+        seconds += ticks_to_seconds(change.tick - from, current);
+        (from, current) = (change.tick, change.bpm);
+    }
+    seconds + ticks_to_seconds(tick - from, current)
+}
+
+// 첫 노트 기준 시작 옥타브 (모비노기 MML 범위 O2~O6으로 클램프)
+fn start_octave_for(first_note: u8) -> i32 {
+    let octave = (first_note as i32 / 12) - 1;
+    octave.clamp(2, 6)
+}
+
+/// 변환 옵션. 프론트엔드가 넘기는 JSON 과 필드 이름이 같다.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConversionOptions {
+    pub char_limit: usize,
+    pub mode: String, // "solo"(혼자 3) / "duo"(2인 4) / "ensemble"(합주 6)
+    // true 면 글자수를 넘는 곡 끝을 버리지 않고 마디 경계에서 여러 악보 칸(1부, 2부 …)으로 나눈다
+    #[serde(default)]
+    pub split_pages: bool,
+    // 소스테누토 페달(CC66) 반영 여부 (서스테인 페달 CC64 는 항상 반영)
+    #[serde(default)]
+    pub sostenuto_pedal: bool,
+    // 셈여림을 V 명령으로 반영할 때의 히스테리시스(V 단계 수). 없으면 V15 고정
+    #[serde(default)]
+    pub dynamics: Option<u8>,
+    // 변환에 넣을 트랙·채널 (include_tracks, exclude_tracks, include_channels, exclude_channels)
+    #[serde(flatten)]
+    pub source: SourceFilter,
+    // 멜로디로 고정할 트랙·채널. 지정하면 그 음은 다른 음과 섞지 않고 항상 첫 보이스("멜로디")에 둔다
+    #[serde(default)]
+    pub melody_track: Option<usize>,
+    #[serde(default)]
+    pub melody_channel: Option<u8>,
+    // 조옮김: 반음 수(예: -3) 또는 "auto" (옥타브 범위 밖 음과 옥타브 이동 글자를 줄이는 쪽으로)
+    #[serde(default)]
+    pub transpose: Transpose,
+    // 보이스별 옥타브 범위 (예: 베이스 O1~O4). 범위 밖 음은 옥타브 단위로 접어 넣는다
+    #[serde(default)]
+    pub octave_ranges: Vec<VoiceOctaveRange>,
+    // true 면 곡 끝을 자르기 전에 글자를 가장 많이 쓰는 보이스부터 단순화해(꾸밈음 빼기,
+    // 반복음 합치기, 굵은 격자) 곡 전체가 한 칸에 들어가게 해 본다
+    #[serde(default)]
+    pub balance_voices: bool,
+    // true 면 곡 끝을 자르기 전에 단순화 사다리(SIMPLIFY_LADDER)를 모든 보이스에 차례로 쌓아
+    // 곡 전체가 한 칸에 들어가게 해 본다. 쓴 단계는 ConversionResult.simplifications 로 알려 준다
+    #[serde(default)]
+    pub auto_simplify: bool,
+    // 피치 벤드 반영: "ignore"(무시) / "sustain"(가장 오래 머문 음높이로) / "steps"(반음 계단으로 나눔)
+    #[serde(default)]
+    pub pitch_bend: PitchBendMode,
+    // 드럼 파트 음으로 리듬 파트 하나를 만든다 (킥·스네어·하이햇을 칠 음높이). 없으면 드럼은 버린다
+    #[serde(default)]
+    pub percussion: Option<PercussionPitches>,
+    // 원곡의 조성 (임시표를 샵·플랫 중 무엇으로 적을지). 없으면 MIDI 조표, 조표도 없으면 음 분포로 어림한다
+    #[serde(default)]
+    pub key: Option<Key>,
+}
+
+/// 보이스 하나의 옥타브 범위. voice 는 번호를 뗀 보이스 이름("멜로디", "화음", "베이스", "피아노" …).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VoiceOctaveRange {
+    pub voice: String,
+    #[serde(flatten)]
+    pub range: OctaveRange,
+}
+
+/// 조옮김 설정. JSON 으로는 반음 수(정수) 또는 문자열 "auto".
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "TransposeRepr", into = "TransposeRepr")]
+pub enum Transpose {
+    Semitones(i8),
+    Auto,
+}
+
+impl Default for Transpose {
+    fn default() -> Self {
+        Transpose::Semitones(0)
+    }
+}
+
+// Transpose 의 JSON 표현 (정수 또는 "auto")
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum TransposeRepr {
+    Semitones(i8),
+    Keyword(String),
+}
+
+impl TryFrom<TransposeRepr> for Transpose {
+    type Error = String;
+
+    fn try_from(repr: TransposeRepr) -> Result<Self, Self::Error> {
+        match repr {
+            TransposeRepr::Semitones(semitones) => Ok(Transpose::Semitones(semitones)),
+            TransposeRepr::Keyword(k) if k == "auto" => Ok(Transpose::Auto),
+            TransposeRepr::Keyword(k) => Err(format!("알 수 없는 조옮김 값: {}", k)),
+        }
+    }
+}
+
+impl From<Transpose> for TransposeRepr {
+    fn from(transpose: Transpose) -> Self {
+        match transpose {
+            Transpose::Semitones(semitones) => TransposeRepr::Semitones(semitones),
+            Transpose::Auto => TransposeRepr::Keyword("auto".to_string()),
+        }
+    }
+}
+
+impl ConversionOptions {
+    fn extract_options(&self) -> ExtractOptions {
+        ExtractOptions {
+            sostenuto: self.sostenuto_pedal,
+            source: self.source.clone(),
+            pitch_bend: self.pitch_bend,
+            percussion: self.percussion.is_some(),
+        }
+    }
+
+    // 번호를 뗀 보이스 이름(label)에 지정한 옥타브 범위 (없으면 MML 유효 범위 전체)
+    fn octave_range_for(&self, label: &str) -> OctaveRange {
+        self.octave_ranges
+            .iter()
+            .find(|r| r.voice == label)
+            .map_or_else(OctaveRange::default, |r| r.range)
+    }
+
+    // 멜로디로 고정한 음인지 (트랙·채널 둘 다 지정하면 둘 다 맞아야 함)
+    fn pins_melody(&self, n: &Note) -> bool {
+        (self.melody_track.is_some() || self.melody_channel.is_some())
+            && self.melody_track.is_none_or(|t| t == n.track)
+            && self.melody_channel.is_none_or(|c| c == n.channel)
+    }
+
+    fn mml_options(&self) -> MmlOptions {
+        MmlOptions {
+            dynamics: self.dynamics,
+            flats: self.key.is_some_and(|k| k.uses_flats()),
+        }
+    }
+}
+
+/// 보이스(파트) 하나의 한 칸 분량 MML
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VoiceResult {
+    pub name: String,
+    pub content: String,
+    pub char_count: usize,
+    pub note_count: usize,
+    pub duration: f64,
+    pub program: u8, // 대표 GM 악기 번호 (미리 듣기 음색용)
+    pub fidelity: VoiceFidelity,
+    #[serde(default)]
+    pub over_limit: bool, // 그리드 한 칸도 한도 안에 들지 않을 만큼 빽빽해 글자수 한도를 넘긴 채 낸 칸
+}
+
+/// 보이스 하나(한 칸)의 원본 대비 손실 지표. 들어보지 않고도 변환 품질을 가늠하는 용도.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct VoiceFidelity {
+    pub dropped_notes: usize,      // 보이스 분배에서 버려진 원본 음 (그 시각 음높이가 가장 가까운 보이스로 집계)
+    pub truncated_notes: usize,    // 다른 음에 자리를 내주느라 꼬리가 잘린 음
+    pub approximated_notes: usize, // MML 길이로 정확히 못 적어 길이를 근사한 음
+    pub max_drift_ticks: u32,      // MML 을 다시 읽었을 때 원래 시작 시점과 가장 크게 어긋난 틱
+    #[serde(default)]
+    pub simplified_notes: usize,   // 글자수를 줄이려고 단순화하며 합치거나 뺀 음
+    #[serde(default)]
+    pub unmatched_notes: usize,    // MML 을 다시 읽었을 때 짝을 못 찾은 음 (원본에만·MML 에만 있는 음)
+}
+
+/// 곡 안의 한 시점: 마디·박(1부터)과 곡 처음부터 걸리는 시간(초)
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct SongPosition {
+    pub tick: u32,
+    pub bar: u32,
+    pub beat: u32,
+    pub seconds: f64,
+}
+
+impl SongPosition {
+    fn at(tick: u32, bar_map: &BarMap, bpm: u32, tempo_changes: &[TempoChange]) -> Self {
+        let (bar, beat) = bar_map.bar_beat(tick);
+        SongPosition {
+            tick,
+            bar,
+            beat,
+            seconds: seconds_at(tick, bpm, tempo_changes),
+        }
+    }
+}
+
+/// [`convert`] 결과. 프론트엔드에 그대로 JSON 으로 넘긴다.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ConversionResult {
+    pub success: bool,
+    pub voices: Vec<VoiceResult>,     // 첫 번째 칸 (= pages[0])
+    pub pages: Vec<Vec<VoiceResult>>, // 칸(페이지)별 보이스. split_pages 가 아니면 1칸
+    pub error: Option<ConversionError>, // 실패 사유 (code 로 구분)
+    pub bpm: u32,
+    pub total_notes: usize,
+    pub original_duration: f64,
+    pub transpose: i32, // 적용한 조옮김 (반음, 자동이면 고른 값)
+    pub simplifications: Vec<SimplifyLevel>, // 글자수 한도 때문에 적용한 단순화 사다리 단계
+    pub cuts: Vec<SongPosition>, // 곡을 자른 곳 (칸 나누기면 칸 경계들)
+    pub key: Option<Key>,        // 악보의 조성 (조옮김 뒤)
+}
+
+impl ConversionResult {
+    /// 변환에 실패했을 때 프론트엔드에 돌려줄 빈 결과
+    pub fn failure(error: ConversionError) -> Self {
+        Self {
+            success: false,
+            voices: vec![],
+            pages: vec![],
+            error: Some(error),
+            bpm: 0,
+            total_notes: 0,
+            original_duration: 0.0,
+            transpose: 0,
+            simplifications: vec![],
+            cuts: vec![],
+            key: None,
+        }
+    }
+
+    /// 글자수 한도 때문에 곡 뒷부분이 악보에 담기지 못했는지.
+    /// 칸마다 담긴 음과 분배·단순화로 빠진 음을 모두 더해도 원본 음 수에 못 미치면 잘린 것이다.
+    pub fn is_truncated(&self) -> bool {
+        let covered: usize = self
+            .pages
+            .iter()
+            .flatten()
+            .map(|v| v.note_count + v.fidelity.dropped_notes + v.fidelity.simplified_notes)
+            .sum();
+        covered < self.total_notes
+    }
+
+    /// 글자수 한도를 넘긴 채 낸 칸 번호들 (0부터). 칸 나누기에서 그리드 한 칸조차 한도 안에
+    /// 들지 않으면 곡을 빠짐없이 담으려고 그 칸을 넘친 채로 낸다.
+    pub fn over_limit_pages(&self) -> Vec<usize> {
+        (0..self.pages.len())
+            .filter(|&i| self.pages[i].iter().any(|v| v.over_limit))
+            .collect()
+    }
+}
+
+/// MIDI 파일 바이트를 옵션에 맞춰 보이스별·칸별 MML 로 변환한다.
+pub fn convert(
+    midi_data: &[u8],
+    options: &ConversionOptions,
+) -> Result<ConversionResult, ConversionError> {
+    let ExtractedMidi {
+        notes,
+        bpm,
+        tempo_changes,
+        bar_map,
+        key,
+    } = extract_midi_notes(midi_data, &options.extract_options())?;
+    let options = &ConversionOptions {
+        key: Some(options.key.unwrap_or(key)),
+        ..options.clone()
+    };
+    let total_notes = notes.len();
+
+    // 원본 길이 계산
+    let original_duration = if notes.is_empty() {
+        0.0
+    } else {
+        let max_end = notes.iter().map(|n| n.end).max().unwrap_or(0);
+        ticks_to_seconds(max_end, bpm)
+    };
+
+    let VoiceLayout {
+        pages,
+        transpose,
+        simplifications,
+        cuts,
+        key,
+    } = convert_voices(
+        notes,
+        &SongTiming {
+            bpm,
+            tempo_changes: &tempo_changes,
+            bar_map: &bar_map,
+        },
+        options,
+    );
+    let voices = pages.first().cloned().unwrap_or_default();
+    let cuts = cuts
+        .into_iter()
+        .map(|tick| SongPosition::at(tick, &bar_map, bpm, &tempo_changes))
+        .collect();
+
+    Ok(ConversionResult {
+        success: true,
+        voices,
+        pages,
+        error: None,
+        bpm,
+        total_notes,
+        original_duration,
+        transpose,
+        simplifications,
+        cuts,
+        key,
+    })
+}
+
+// 악보 한 칸에 담을 구간 [from, to) 에서 시작하는 음을 골라 0틱 기준으로 옮긴다.
+// clip=true 면 칸 끝을 넘어가는 꼬리를 잘라, 다음 칸이 밀리지 않고 이어서 연주되게 한다.
+fn slice_voice(voice: &[Note], from: u32, to: u32, clip: bool) -> Vec<Note> {
+    voice
+        .iter()
+        .filter(|n| n.start >= from && n.start < to)
+        .map(|n| {
+            let end = if clip { n.end.min(to) } else { n.end };
+            Note {
+                start: n.start - from,
+                end: end - from,
+                duration: end - n.start,
+                ..n.clone()
+            }
+        })
+        .collect()
+}
+
+// 칸 시작 시점의 템포(헤더 T)와, 칸 안쪽 템포 변경을 0틱 기준으로 옮긴 목록.
+// generate_mml_final 은 목록의 0번을 시작 템포로 보고 건너뛰므로 맨 앞에 헤더 템포를 둔다.
+fn slice_tempo(tempo_changes: &[TempoChange], bpm: u32, from: u32) -> (u32, Vec<TempoChange>) {
+    if from == 0 {
+        return (bpm, tempo_changes.to_vec());
+    }
+    let page_bpm = tempo_changes
+        .iter()
+        .take_while(|t| t.tick <= from)
+        .last()
+        .map_or(bpm, |t| t.bpm);
+    let mut tempos = vec![TempoChange {
+        tick: 0,
+        bpm: page_bpm,
+    }];
+    tempos.extend(
+        tempo_changes
+            .iter()
+            .filter(|t| t.tick > from)
+            .map(|t| TempoChange {
+                tick: t.tick - from,
+                bpm: t.bpm,
+            }),
+    );
+    (page_bpm, tempos)
+}
+
+// 한 보이스의 [from, to) 구간을 헤더(T/O/L)가 붙은 독립 MML 로 만든다. 담긴 음이 없으면 None.
+// 칸 나누기(split_pages)일 때만 칸 끝을 넘는 음 꼬리를 자른다.
+fn render_page(
+    voice: &[Note],
+    range: OctaveRange,
+    from: u32,
+    to: u32,
+    bpm: u32,
+    tempo_changes: &[TempoChange],
+    options: &ConversionOptions,
+) -> Option<(Vec<Note>, u32, String)> {
+    let notes = fold_octaves(slice_voice(voice, from, to, options.split_pages), range);
+    if notes.is_empty() {
+        return None;
+    }
+    let (page_bpm, tempos) = slice_tempo(tempo_changes, bpm, from);
+    let start_octave = start_octave_for(notes[0].note);
+    let mml = generate_mml_final(&notes, page_bpm, start_octave, &tempos, &options.mml_options());
+    Some((notes, page_bpm, mml))
+}
+
+// fits 가 앞쪽일수록 잘 맞는(단조) 조건일 때, tick(k) (k_lo..=k_hi, k 가 클수록 뒤) 중
+// fits 를 만족하는 가장 큰 값
+fn last_fitting(
+    k_lo: u32,
+    k_hi: u32,
+    tick: impl Fn(u32) -> u32,
+    fits: impl Fn(u32) -> bool,
+) -> Option<u32> {
+    let (mut lo, mut hi) = (k_lo, k_hi);
+    let mut best = None;
+    while lo <= hi {
+        let mid = lo + (hi - lo) / 2;
+        if fits(tick(mid)) {
+            best = Some(tick(mid));
+            lo = mid + 1;
+        } else if mid == 0 {
+            break;
+        } else {
+            hi = mid - 1;
+        }
+    }
+    best
+}
+
+// last_fitting 과 같은 값을 찾되 어림값 guess(k) 둘레부터 확인한다.
+// 어림값에서 1·2·4… 칸씩 벌려 가며 fits 가 바뀌는 곳을 찾고 그 사이만 이진 탐색하므로,
+// 어림이 맞으면 fits 를 두 번만 부르고 빗나가도 어림값 가까이만 본다.
+fn last_fitting_near(
+    k_lo: u32,
+    k_hi: u32,
+    tick: impl Fn(u32) -> u32,
+    guess: u32,
+    fits: impl Fn(u32) -> bool,
+) -> Option<u32> {
+    if k_lo > k_hi {
+        return None;
+    }
+    let guess = guess.clamp(k_lo, k_hi);
+    let mut reach = 1;
+    if fits(tick(guess)) {
+        let mut lo = guess; // 맞는 것으로 확인한 가장 큰 k
+        while lo < k_hi {
+            let probe = lo.saturating_add(reach).min(k_hi);
+            if !fits(tick(probe)) {
+                return last_fitting(lo + 1, probe - 1, &tick, &fits).or(Some(tick(lo)));
+            }
+            lo = probe;
+            reach *= 2;
+        }
+        Some(tick(lo))
+    } else {
+        let mut hi = guess; // 안 맞는 것으로 확인한 가장 작은 k
+        while hi > k_lo {
+            let probe = hi.saturating_sub(reach).max(k_lo);
+            if fits(tick(probe)) {
+                return last_fitting(probe + 1, hi - 1, &tick, &fits).or(Some(tick(probe)));
+            }
+            hi = probe;
+            reach *= 2;
+        }
+        None
+    }
+}
+
+// 보이스를 곡 처음부터 한 번 적어 얻은 음별 누적 글자 수. 칸을 어디서 자를지 어림하는 데 쓴다.
+struct CharOffsets {
+    starts: Vec<u32>, // 음 시작 틱 (옥타브를 접은 뒤)
+    ends: Vec<usize>, // 헤더부터 그 음까지 적은 글자 수
+    header: usize,    // 첫 음·쉼표 앞 헤더(T/V/O/L) 글자 수
+    len: usize,       // 곡 전체 MML 글자 수
+}
+
+impl CharOffsets {
+    fn new(
+        voice: &[Note],
+        range: OctaveRange,
+        bpm: u32,
+        tempo_changes: &[TempoChange],
+        options: &ConversionOptions,
+    ) -> Option<Self> {
+        let notes = fold_octaves(voice.to_vec(), range);
+        let start_octave = start_octave_for(notes.first()?.note);
+        let (mml, ends) = generate_mml_with_offsets(
+            &notes,
+            bpm,
+            start_octave,
+            tempo_changes,
+            &options.mml_options(),
+        );
+        debug_assert_eq!(ends.len(), notes.len(), "음마다 누적 글자 수 하나");
+        let header = mml
+            .find(|c: char| matches!(c.to_ascii_uppercase(), 'A'..='G' | 'R'))
+            .unwrap_or(0);
+        Some(CharOffsets {
+            starts: notes.iter().map(|n| n.start).collect(),
+            ends,
+            header,
+            len: mml.len(),
+        })
+    }
+
+    // from 에서 시작하는 칸에 limit 글자까지 담았을 때 처음으로 못 담는 음의 시작. 끝까지 담기면 None.
+    // 칸 헤더는 곡 첫 헤더와 길이가 비슷하다고 본다.
+    fn cut_after(&self, from: u32, limit: usize) -> Option<u32> {
+        let first = self.starts.partition_point(|&s| s < from);
+        let base = match first {
+            0 => 0,
+            i => self.ends[i - 1] - self.header,
+        };
+        let fit = self.ends[first..].partition_point(|&end| end - base <= limit);
+        self.starts.get(first + fit).copied()
+    }
+}
+
+// 곡의 시간 정보: 시작 BPM, 템포 변경, 마디 지도 (칸을 마디선에서 나누고 길이를 재는 데 쓴다)
+#[derive(Clone, Copy)]
+struct SongTiming<'a> {
+    bpm: u32,
+    tempo_changes: &'a [TempoChange],
+    bar_map: &'a BarMap,
+}
+
+/// 보이스 목록을 받아 글자수 제한(char_limit)에 맞게 나눈 뒤, 각 보이스를 MML 문자열로 변환한다.
+/// - 기본: 모든 보이스가 한 칸에 들어가도록 곡 끝을 잘라낸다 (반환 칸 1개).
+/// - split_pages: 곡을 마디 경계(`timing.bar_map` 의 마디선)에서 여러 칸으로 나눈다.
+///   칸마다 T/O/L 헤더가 새로 붙어 1부, 2부 … 를 차례로 불러와 이어서 연주할 수 있다.
+///
+/// `namer`는 (보이스 인덱스, 담긴 노트들) -> 파트 이름 을 결정한다. 칸이 바뀌어도 이름은 같다.
+/// `source`는 분배 전 원본 음으로, 보이스별 충실도(VoiceFidelity) 집계에 쓴다.
+/// `octave_ranges`는 보이스와 같은 순서의 옥타브 범위 (모자라면 기본 범위). 범위 밖 음은 접어 넣는다.
+/// 칸 목록과 함께, 자르기 전에 적용한 단순화 사다리 단계(auto_simplify)와
+/// 자른 시점(틱: 칸 나누기면 칸 경계들, 아니면 곡을 잘랐을 때 그 끝)을 돌려준다.
+fn build_voices_with_limit<F>(
+    voices: Vec<Vec<Note>>,
+    octave_ranges: &[OctaveRange],
+    source: &[Note],
+    timing: &SongTiming,
+    options: &ConversionOptions,
+    mut namer: F,
+) -> (Vec<Vec<VoiceResult>>, Vec<SimplifyLevel>, Vec<u32>)
+where
+    F: FnMut(usize, &[Note]) -> String,
+{
+    let SongTiming {
+        bpm,
+        tempo_changes,
+        bar_map,
+    } = *timing;
+    // 빈 voice 제거 (옥타브 범위도 같이)
+    let (voices, octave_ranges): (Vec<Vec<Note>>, Vec<OctaveRange>) = voices
+        .into_iter()
+        .enumerate()
+        .filter(|(_, v)| !v.is_empty())
+        .map(|(i, v)| (v, octave_ranges.get(i).copied().unwrap_or_default()))
+        .unzip();
+    if voices.is_empty() {
+        return (Vec::new(), Vec::new(), Vec::new());
+    }
+
+    // 곡 끝을 자르기 전에 바쁜 보이스부터, 그래도 넘치면 단순화 사다리로 모든 보이스를 단순화해 본다
+    // (칸 나누기는 곡을 다 담으므로 하지 않음)
+    let cropping = !options.split_pages;
+    let mut simplified = (options.balance_voices && cropping)
+        .then(|| balance_voices(&voices, &octave_ranges, bpm, tempo_changes, options))
+        .flatten();
+    let mut simplifications = Vec::new();
+    if options.auto_simplify && cropping {
+        let current = simplified.as_deref().unwrap_or(&voices);
+        if let Some((laddered, levels)) =
+            climb_simplify_ladder(current, &octave_ranges, bpm, tempo_changes, options)
+        {
+            simplified = Some(laddered);
+            simplifications = levels;
+        }
+    }
+    let rendered: &[Vec<Note>] = simplified.as_deref().unwrap_or(&voices);
+
+    let max_end_time = rendered
+        .iter()
+        .flat_map(|v| v.iter())
+        .map(|n| n.end)
+        .max()
+        .unwrap_or(0);
+    if max_end_time == 0 {
+        return (Vec::new(), simplifications, Vec::new());
+    }
+
+    // [from, to) 구간으로 자른 모든 voice가 char_limit 이하인지 검사
+    let all_within_limit = |from: u32, to: u32| {
+        rendered.iter().zip(&octave_ranges).all(|(voice, &range)| {
+            render_page(voice, range, from, to, bpm, tempo_changes, options)
+                .is_none_or(|(_, _, mml)| mml.len() <= options.char_limit)
+        })
+    };
+
+    // 자를 시점 어림: 보이스마다 곡 전체를 한 번 적은 음별 누적 글자 수로 본, from 에서 시작한 칸에
+    // 모든 보이스가 담기는 시점 (모두 끝까지 담기면 곡 끝). 실제 MML 로 확인은 이 둘레에서만 한다.
+    let offsets: Vec<Option<CharOffsets>> = rendered
+        .iter()
+        .zip(&octave_ranges)
+        .map(|(voice, &range)| CharOffsets::new(voice, range, bpm, tempo_changes, options))
+        .collect();
+    let guess_cut = |from: u32| {
+        offsets
+            .iter()
+            .flatten()
+            .filter_map(|o| o.cut_after(from, options.char_limit))
+            .min()
+            .unwrap_or(max_end_time)
+    };
+    // [from, 곡 끝) 이 모두 들어가는지. 곡 처음부터라면 어림에 쓴 MML 이 곧 그 칸이라 다시 적지 않는다.
+    let rest_fits = |from: u32| match from {
+        0 if offsets.iter().zip(rendered).all(|(o, v)| o.is_some() || v.is_empty()) => offsets
+            .iter()
+            .flatten()
+            .all(|o| o.len <= options.char_limit),
+        _ => all_within_limit(from, max_end_time),
+    };
+
+    // 칸 경계 [from, to) 목록
+    let mut ranges = Vec::new();
+    if options.split_pages {
+        // 남은 곡이 통째로 들어가면 마지막 칸, 아니면 들어가는 가장 먼 마디선(마디 지도)에서 자른다.
+        // 한 마디도 안 들어갈 만큼 빽빽하면 그리드 단위로 자르고, 그래도 안 되면 한 칸만 전진한다.
+        // 그 칸은 한도를 넘으므로 아래에서 over_limit 으로 표시한다.
+        let mut from = 0u32;
+        while from < max_end_time {
+            let guess = guess_cut(from);
+            let to = if guess >= max_end_time && rest_fits(from) {
+                max_end_time
+            } else {
+                let last = max_end_time - 1;
+                let fits = |t| all_within_limit(from, t);
+                let bars = bar_map.bar_lines(from + 1, last);
+                last_fitting_near(
+                    1,
+                    bars.len() as u32,
+                    |k| bars[k as usize - 1],
+                    bars.partition_point(|&t| t <= guess) as u32,
+                    fits,
+                )
+                .or_else(|| {
+                    last_fitting_near(
+                        from / GRID_SIZE + 1,
+                        last / GRID_SIZE,
+                        |k| k * GRID_SIZE,
+                        guess / GRID_SIZE,
+                        fits,
+                    )
+                })
+                .unwrap_or(from + GRID_SIZE)
+            };
+            ranges.push((from, to));
+            from = to;
+        }
+    } else {
+        // 전체 길이가 OK면 그대로, 아니면 어림값 둘레에서 최대 종료 시점 찾기
+        let guess = guess_cut(0);
+        let best_end_time = if guess >= max_end_time && rest_fits(0) {
+            max_end_time
+        } else {
+            let grid = |k| k * GRID_SIZE;
+            last_fitting_near(1, max_end_time / GRID_SIZE, grid, guess / GRID_SIZE, |t| {
+                all_within_limit(0, t)
+            })
+            .unwrap_or(0)
+        };
+        ranges.push((0, best_end_time));
+    }
+
+    // 보이스 이름은 담긴 음 전체 기준으로 한 번만 정한다 (칸마다 번호가 바뀌지 않게)
+    let names: Vec<Option<String>> = rendered
+        .iter()
+        .enumerate()
+        .map(|(idx, voice)| {
+            let kept: Vec<Note> = voice
+                .iter()
+                .filter(|n| ranges.iter().any(|&(from, to)| n.start >= from && n.start < to))
+                .cloned()
+                .collect();
+            (!kept.is_empty()).then(|| namer(idx, &kept))
+        })
+        .collect();
+
+    let dropped = attribute_dropped_notes(&voices, source);
+    // 단순화로 통째로 뺀 보이스의 음(과 그 보이스로 집계된 버려진 음)은
+    // 평균 음높이가 가장 가까운 남은 보이스가 대신 집계한다
+    let heirs: Vec<usize> = (0..voices.len())
+        .map(|i| match rendered[i].is_empty() {
+            false => i,
+            true => (0..rendered.len())
+                .filter(|&j| !rendered[j].is_empty() && !is_rhythm(&rendered[j]))
+                .min_by_key(|&j| avg_pitch(&rendered[j]).abs_diff(avg_pitch(&voices[i])))
+                .unwrap_or(i),
+        })
+        .collect();
+
+    // 칸별로 모든 voice MML 생성
+    let mut pages = Vec::new();
+    for &(from, to) in &ranges {
+        let mut results = Vec::new();
+        for (idx, (((voice, simplified), &range), name)) in
+            voices.iter().zip(rendered).zip(&octave_ranges).zip(&names).enumerate()
+        {
+            let (Some(name), Some((final_voice, page_bpm, mml_code))) =
+                (name, render_page(simplified, range, from, to, bpm, tempo_changes, options))
+            else {
+                continue;
+            };
+            let actual_end = final_voice.iter().map(|n| n.end).max().unwrap_or(0);
+            let in_page = |n: &Note| n.start >= from && n.start < to;
+            let inherited = || (0..voices.len()).filter(|&j| j != idx && heirs[j] == idx);
+            let mut fidelity = round_trip_fidelity(&final_voice, &mml_code);
+            fidelity.dropped_notes = std::iter::once(idx)
+                .chain(inherited())
+                .map(|j| dropped[j].iter().filter(|n| in_page(n)).count())
+                .sum();
+            fidelity.truncated_notes = count_truncated(voice.iter().filter(|n| in_page(n)), source);
+            fidelity.simplified_notes = voice
+                .iter()
+                .filter(|n| in_page(n))
+                .count()
+                .saturating_sub(final_voice.len())
+                + inherited()
+                    .map(|j| voices[j].iter().filter(|n| in_page(n)).count())
+                    .sum::<usize>();
+
+            results.push(VoiceResult {
+                name: name.clone(),
+                char_count: mml_code.len(),
+                over_limit: mml_code.len() > options.char_limit,
+                note_count: final_voice.len(),
+                duration: ticks_to_seconds(actual_end, page_bpm),
+                content: mml_code,
+                program: dominant_program(voice),
+                fidelity,
+            });
+        }
+        if !results.is_empty() {
+            pages.push(results);
+        }
+    }
+
+    let cuts = match options.split_pages {
+        true => ranges.iter().skip(1).map(|&(from, _)| from).collect(),
+        false => ranges.iter().map(|&(_, to)| to).filter(|&to| to < max_end_time).collect(),
+    };
+    (pages, simplifications, cuts)
+}
+
+// 곡 전체가 한 칸에 들어가지 않으면, 글자를 가장 많이 쓰는 보이스에 단순화 단계를 하나씩 더한다.
+// 그 보이스가 한도 안에 들거나 더 단순화할 수 없으면 멈춘다. 처음부터 다 들어가면 None.
+fn balance_voices(
+    voices: &[Vec<Note>],
+    octave_ranges: &[OctaveRange],
+    bpm: u32,
+    tempo_changes: &[TempoChange],
+    options: &ConversionOptions,
+) -> Option<Vec<Vec<Note>>> {
+    let chars = |voice: &[Note], range: OctaveRange| {
+        render_page(voice, range, 0, u32::MAX, bpm, tempo_changes, options)
+            .map_or(0, |(_, _, mml)| mml.len())
+    };
+    let mut lengths: Vec<usize> =
+        voices.iter().zip(octave_ranges).map(|(v, &r)| chars(v, r)).collect();
+    if lengths.iter().all(|&len| len <= options.char_limit) {
+        return None;
+    }
+
+    let mut balanced = voices.to_vec();
+    let mut levels = vec![0; voices.len()];
+    while let Some((busiest, &len)) = lengths.iter().enumerate().max_by_key(|&(_, len)| len)
+        && len > options.char_limit
+        && levels[busiest] < BALANCE_STEPS.len()
+    {
+        balanced[busiest] = simplify_voice(&balanced[busiest], BALANCE_STEPS[levels[busiest]]);
+        levels[busiest] += 1;
+        lengths[busiest] = chars(&balanced[busiest], octave_ranges[busiest]);
+    }
+    Some(balanced)
+}
+
+// 곡 전체가 한 칸에 들어가지 않으면 단순화 사다리를 한 단계씩 모든 보이스에 쌓는다.
+// 가운데 화음 보이스 빼기 단계는 글자를 많이 쓰는 보이스부터 하나씩 뺀다.
+// 다 들어가거나 사다리 끝까지 가면 멈춘다. 처음부터 다 들어가면 None.
+fn climb_simplify_ladder(
+    voices: &[Vec<Note>],
+    octave_ranges: &[OctaveRange],
+    bpm: u32,
+    tempo_changes: &[TempoChange],
+    options: &ConversionOptions,
+) -> Option<(Vec<Vec<Note>>, Vec<SimplifyLevel>)> {
+    let chars = |voice: &[Note], range: OctaveRange| {
+        render_page(voice, range, 0, u32::MAX, bpm, tempo_changes, options)
+            .map_or(0, |(_, _, mml)| mml.len())
+    };
+    let lengths = |voices: &[Vec<Note>]| -> Vec<usize> {
+        voices.iter().zip(octave_ranges).map(|(v, &r)| chars(v, r)).collect()
+    };
+    let fits = |lengths: &[usize]| lengths.iter().all(|&len| len <= options.char_limit);
+    if fits(&lengths(voices)) {
+        return None;
+    }
+
+    let mut current = voices.to_vec();
+    let mut levels = Vec::new();
+    for level in SIMPLIFY_LADDER {
+        levels.push(level);
+        match level.voice_step() {
+            Some(step) => current.iter_mut().for_each(|v| *v = simplify_voice(v, step)),
+            None => {
+                let mut inner = inner_voices(&current);
+                let current_lengths = lengths(&current);
+                inner.sort_by_key(|&i| std::cmp::Reverse(current_lengths[i]));
+                for i in inner {
+                    current[i].clear();
+                    if fits(&lengths(&current)) {
+                        break;
+                    }
+                }
+            }
+        }
+        if fits(&lengths(&current)) {
+            break;
+        }
+    }
+    Some((current, levels))
+}
+
+// 가운데 화음 보이스 (첫 보이스와 평균 음높이가 가장 높은·낮은 보이스, 리듬 파트를 뺀 나머지)
+fn inner_voices(voices: &[Vec<Note>]) -> Vec<usize> {
+    let sounding: Vec<usize> = (0..voices.len())
+        .filter(|&i| !voices[i].is_empty() && !is_rhythm(&voices[i]))
+        .collect();
+    let highest = sounding.iter().copied().max_by_key(|&i| avg_pitch(&voices[i]));
+    let lowest = sounding.iter().copied().min_by_key(|&i| avg_pitch(&voices[i]));
+    sounding
+        .into_iter()
+        .filter(|&i| i != 0 && Some(i) != highest && Some(i) != lowest)
+        .collect()
+}
+
+// 드럼 음으로 만든 리듬 파트 보이스인지
+fn is_rhythm(voice: &[Note]) -> bool {
+    voice.first().is_some_and(|n| n.percussion)
+}
+
+// 원본 음과 분배된 음을 잇는 키. 보이스 분배는 음을 자를 때도 시작·음높이·악기·트랙·채널은 그대로 둔다.
+// 트랙·채널까지 보아야 두 파트가 같은 음을 겹쳐 칠 때 하나만 담겨도 둘 다 담긴 것으로 세지 않는다.
+type NoteKey = (u32, u8, u8, usize, u8);
+
+fn note_key(n: &Note) -> NoteKey {
+    (n.start, n.note, n.program, n.track, n.channel)
+}
+
+// 어느 보이스에도 담기지 않은 원본 음을 보이스별로 나눈다.
+// 그 음이 시작할 때 울리던 음 중 음높이가 가장 가까운 보이스(= 자리를 못 내준 보이스),
+// 울리던 음이 없으면 평균 음높이가 가장 가까운 보이스로 집계한다.
+// source 의 드럼 음은 리듬 파트에 담지 못한 음뿐이라 리듬 파트로 집계한다.
+fn attribute_dropped_notes<'a>(voices: &[Vec<Note>], source: &'a [Note]) -> Vec<Vec<&'a Note>> {
+    let placed: HashSet<NoteKey> =
+        voices.iter().filter(|v| !is_rhythm(v)).flatten().map(note_key).collect();
+    let rhythm = voices.iter().position(|v| is_rhythm(v));
+    let mut dropped: Vec<Vec<&Note>> = vec![Vec::new(); voices.len()];
+    for n in source.iter().filter(|n| n.percussion || !placed.contains(&note_key(n))) {
+        if n.percussion
+            && let Some(r) = rhythm
+        {
+            dropped[r].push(n);
+            continue;
+        }
+        let pitch_gap = |p: u8| (p as i32 - n.note as i32).abs();
+        let sounding = voices
+            .iter()
+            .enumerate()
+            .filter(|(_, v)| !is_rhythm(v))
+            .filter_map(|(i, v)| {
+                v.iter()
+                    .find(|m| m.start <= n.start && m.end > n.start)
+                    .map(|m| (pitch_gap(m.note), i))
+            })
+            .min();
+        let nearest = sounding.or_else(|| {
+            voices
+                .iter()
+                .enumerate()
+                .filter(|(_, v)| !is_rhythm(v))
+                .map(|(i, v)| (pitch_gap(avg_pitch(v)), i))
+                .min()
+        });
+        if let Some((_, i)) = nearest {
+            dropped[i].push(n);
+        }
+    }
+    dropped
+}
+
+// 원본보다 일찍 끝나는(꼬리를 빼앗긴) 음 수
+fn count_truncated<'a>(notes: impl Iterator<Item = &'a Note>, source: &[Note]) -> usize {
+    let source_end: HashMap<NoteKey, u32> = source
+        .iter()
+        .filter(|n| !n.percussion)
+        .map(|n| (note_key(n), n.end))
+        .collect();
+    notes
+        .filter(|n| source_end.get(&note_key(n)).is_some_and(|&end| n.end < end))
+        .count()
+}
+
+// 생성한 MML 을 다시 읽어 원래 음마다 같은 높이에서 시작이 가장 가까운 음과 짝짓는다.
+// 길이가 달라진 음 = 근사한 음, 시작 시점 차이의 최대값 = 누적 타이밍 드리프트,
+// 짝이 없는 음(타이로 합쳐졌거나 빠진 음, MML 에만 있는 음) = 짝을 못 찾은 음.
+fn round_trip_fidelity(notes: &[Note], mml: &str) -> VoiceFidelity {
+    let Ok(parsed) = parse_mml(mml) else {
+        return VoiceFidelity {
+            unmatched_notes: notes.len(),
+            ..VoiceFidelity::default()
+        };
+    };
+    // 음높이별로 다시 읽은 음의 (시작, 길이, 짝지었는지)를 시작 순으로, 그리고 앞에서부터 다 짝지은 개수
+    type Candidates = (Vec<(u32, u32, bool)>, usize);
+    let mut by_pitch: HashMap<u8, Candidates> = HashMap::new();
+    for p in &parsed.notes {
+        by_pitch.entry(p.note).or_default().0.push((p.start, p.duration, false));
+    }
+    by_pitch.values_mut().for_each(|(v, _)| v.sort_unstable());
+
+    let mut fidelity = VoiceFidelity::default();
+    for n in notes {
+        let Some((candidates, first)) = by_pitch.get_mut(&n.note) else {
+            fidelity.unmatched_notes += 1;
+            continue;
+        };
+        let pos = candidates.partition_point(|&(start, _, _)| start < n.start).max(*first);
+        let before = candidates[*first..pos].iter().rposition(|c| !c.2).map(|i| *first + i);
+        let after = candidates[pos..].iter().position(|c| !c.2).map(|i| pos + i);
+        let Some(i) = [before, after]
+            .into_iter()
+            .flatten()
+            .min_by_key(|&i| candidates[i].0.abs_diff(n.start))
+        else {
+            fidelity.unmatched_notes += 1;
+            continue;
+        };
+        let (start, duration, used) = &mut candidates[i];
+        *used = true;
+        fidelity.approximated_notes += (*duration != n.duration) as usize;
+        fidelity.max_drift_ticks = fidelity.max_drift_ticks.max(start.abs_diff(n.start));
+        while candidates.get(*first).is_some_and(|c| c.2) {
+            *first += 1;
+        }
+    }
+    fidelity.unmatched_notes +=
+        by_pitch.values().flat_map(|(c, _)| c).filter(|c| !c.2).count();
+    fidelity
+}
+
+// 보이스 평균 음높이
+fn avg_pitch(voice: &[Note]) -> u8 {
+    if voice.is_empty() {
+        return 0;
+    }
+    (voice.iter().map(|n| n.note as u32).sum::<u32>() / voice.len() as u32) as u8
+}
+
+/// 트랙 하나의 요약 (변환 전에 넣고 뺄 트랙을 고르는 목록용)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrackInfo {
+    pub index: usize,
+    pub name: Option<String>,
+    pub channels: Vec<u8>,
+    pub note_count: usize,
+    pub program: u8, // 대표 GM 악기 번호
+}
+
+/// 추출한 음을 트랙별로 묶어 트랙 순서대로 요약한다. 음이 없는 트랙(드럼만 있는 트랙 등)은 빠진다.
+pub fn list_tracks(notes: &[Note]) -> Vec<TrackInfo> {
+    let mut by_track: BTreeMap<usize, Vec<Note>> = BTreeMap::new();
+    for n in notes {
+        by_track.entry(n.track).or_default().push(n.clone());
+    }
+    by_track
+        .into_iter()
+        .map(|(index, track_notes)| {
+            let mut channels: Vec<u8> = track_notes.iter().map(|n| n.channel).collect();
+            channels.sort_unstable();
+            channels.dedup();
+            TrackInfo {
+                index,
+                name: track_notes[0].track_name.as_deref().map(str::to_string),
+                channels,
+                note_count: track_notes.len(),
+                program: dominant_program(&track_notes),
+            }
+        })
+        .collect()
+}
+
+// 보이스의 대표 악기(노트 수 다수결). gap-fill 로 빌려온 음이 첫머리에 와도 흔들리지 않게,
+// 첫 음이 아니라 다수결로 음색 이름을 정한다.
+fn dominant_program(voice: &[Note]) -> u8 {
+    let mut counts: HashMap<u8, usize> = HashMap::new();
+    for n in voice {
+        *counts.entry(n.program).or_default() += 1;
+    }
+    counts
+        .into_iter()
+        .max_by_key(|&(_, c)| c)
+        .map(|(p, _)| p)
+        .unwrap_or(0)
+}
+
+// 분배한 보이스에 이름을 붙이는 방식
+enum Naming {
+    Role { mark_bass: bool },
+    Instrument,
+}
+
+// convert_voices 결과
+struct VoiceLayout {
+    pages: Vec<Vec<VoiceResult>>,
+    transpose: i32,                       // 적용한 조옮김 (반음)
+    simplifications: Vec<SimplifyLevel>, // 적용한 단순화 사다리 단계
+    cuts: Vec<u32>,                      // 칸 경계·자른 시점 (틱)
+    key: Option<Key>,                    // 조옮김한 조성
+}
+
+// 모드에 따라 보이스를 분배하고 이름을 붙인다.
+// 반환: VoiceLayout (칸별 보이스, 적용한 조옮김, 단순화 단계, 칸 경계, 조옮김한 조성)
+// - solo(단독): 가장 중요한 3보이스. 멜로디 + 화음1, 화음2
+// - duo(2인): 4보이스. 앞 3개(멜로디·화음) + 마지막 베이스 (1명이 앞 3개, 1명이 베이스)
+// - ensemble(화음): 최대 6보이스를 악기 인지로 분배 (악기별 이름 / 단일이면 멜로디·화음)
+// 분배는 음 사이의 높낮이만 보므로, 조옮김은 분배가 끝난 보이스를 보고 정해 원본과 함께 옮긴다.
+fn convert_voices(
+    notes: Vec<Note>,
+    timing: &SongTiming,
+    options: &ConversionOptions,
+) -> VoiceLayout {
+    // 리듬 파트를 만들면 드럼 음을 떼어 맨 뒤 보이스 하나로 둔다 (보이스 자리 하나를 쓴다)
+    let (drums, notes): (Vec<Note>, Vec<Note>) = notes.into_iter().partition(|n| n.percussion);
+    let (rhythm, left_out) = match options.percussion {
+        Some(pitches) => rhythm_voice(&drums, &pitches),
+        None => (Vec::new(), Vec::new()),
+    };
+    // 분배 전 원본 (충실도 집계용). 드럼 음은 리듬 파트에 담지 못한 것만 넣어 버린 음으로 센다
+    let source: Vec<Note> = notes.iter().cloned().chain(left_out).collect();
+    // 멜로디를 고정했으면 그 한 줄을 먼저 떼어 첫 보이스로 두고, 나머지 음만 남은 보이스에 분배
+    let (melody, notes) = split_melody_line(notes, |n| options.pins_melody(n));
+    let pinned = !melody.is_empty();
+    let with_melody = |voices: Vec<Vec<Note>>| {
+        if pinned {
+            std::iter::once(melody.clone()).chain(voices).collect()
+        } else {
+            voices
+        }
+    };
+    let budget = |voices: usize| voices - pinned as usize - !rhythm.is_empty() as usize;
+
+    let (voices, naming) = match options.mode.as_str() {
+        "duo" => {
+            // 2인: 베이스 라인을 따로 떼어 1보이스, 나머지(멜로디·화음)는 3보이스 → 역할 분리
+            let (bass, rest) = split_bass_line(notes);
+            let mut voices = with_melody(allocate_voices_capped(rest, budget(DUO_VOICES - 1)));
+            voices.extend(allocate_voices_capped(bass, 1)); // 단음 베이스 라인
+            (voices, Naming::Role { mark_bass: true })
+        }
+        "ensemble" => {
+            let voices = with_melody(allocate_voices_by_instrument(notes, budget(MAX_VOICES)));
+            // 음색군(family) 기준으로 여러 음색이면 악기군 이름, 단일 음색이면 역할 이름
+            let distinct: std::collections::HashSet<u8> = voices
+                .iter()
+                .filter(|v| !v.is_empty())
+                .map(|v| dominant_program(v) / 8)
+                .collect();
+            if distinct.len() > 1 {
+                (voices, Naming::Instrument)
+            } else {
+                (voices, Naming::Role { mark_bass: false })
+            }
+        }
+        _ => {
+            // solo
+            let voices = with_melody(allocate_voices_capped(notes, budget(SOLO_VOICES)));
+            (voices, Naming::Role { mark_bass: false })
+        }
+    };
+
+    let shift = match options.transpose {
+        Transpose::Semitones(semitones) => semitones as i32,
+        Transpose::Auto => auto_transpose(&voices),
+    };
+    let mut voices: Vec<Vec<Note>> = voices.into_iter().map(|v| transpose_notes(v, shift)).collect();
+    if !rhythm.is_empty() {
+        voices.push(rhythm); // 드럼 음높이는 옮기지 않는다
+    }
+    let source = transpose_notes(source, shift);
+    let key = options.key.map(|k| k.transposed(shift));
+    let options = &ConversionOptions {
+        key,
+        ..options.clone()
+    };
+
+    let (pages, simplifications, cuts) = match naming {
+        Naming::Role { mark_bass } => {
+            name_by_role(voices, &source, timing, options, mark_bass, pinned)
+        }
+        Naming::Instrument => name_by_instrument(voices, &source, timing, options, pinned),
+    };
+    VoiceLayout {
+        pages,
+        transpose: shift,
+        simplifications,
+        cuts,
+        key,
+    }
+}
+
+// 자동 조옮김이 살펴볼 범위 (위아래 한 옥타브)
+const AUTO_TRANSPOSE_RANGE: i32 = 12;
+
+// 자동 조옮김: 옥타브 범위 밖이라 클램프되는(틀린 음이 되는) 음 수를 먼저 줄이고,
+// 그다음 옥타브 이동 명령(>, <, O{n}) 글자 수를 줄이는 반음 수. 같으면 덜 옮기는 쪽.
+fn auto_transpose(voices: &[Vec<Note>]) -> i32 {
+    (-AUTO_TRANSPOSE_RANGE..=AUTO_TRANSPOSE_RANGE)
+        .min_by_key(|&shift| {
+            let mut clamped = 0;
+            let mut octave_chars = 0;
+            for voice in voices.iter().filter(|v| !v.is_empty()) {
+                let pitch = |n: &Note| (n.note as i32 + shift).clamp(0, 127) as u8;
+                let mut current = start_octave_for(pitch(&voice[0]));
+                for n in voice {
+                    let (octave, in_range) = mml_octave(pitch(n));
+                    clamped += !in_range as usize;
+                    octave_chars += match (octave - current).abs() {
+                        0 => 0,
+                        1 => 1, // > 또는 <
+                        _ => 2, // O{n}
+                    };
+                    current = octave;
+                }
+            }
+            (clamped, octave_chars, shift.abs(), shift)
+        })
+        .unwrap_or(0)
+}
+
+// 여러 악기: 악기군 이름 + 악기별 일련번호 (피아노1, 피아노2, 기타1 …)
+// 같은 악기명 카드가 UI에서 붙어 보이도록, 악기명 첫 등장 순서로 묶어 정렬한 뒤 번호를 매긴다.
+// (현악=GM5/6, 신스=GM10/11/12 처럼 다른 GM군이 같은 이름이 돼도 흩어지지 않게)
+// pinned=true 면 첫 보이스는 고정 멜로디라 자리를 옮기지 않고 "멜로디"로 라벨, 리듬 파트는 "드럼"
+fn name_by_instrument(
+    mut voices: Vec<Vec<Note>>,
+    source: &[Note],
+    timing: &SongTiming,
+    options: &ConversionOptions,
+    pinned: bool,
+) -> (Vec<Vec<VoiceResult>>, Vec<SimplifyLevel>, Vec<u32>) {
+    let fixed = pinned as usize;
+    let mut group_order: HashMap<&str, usize> = HashMap::new();
+    for v in &voices[fixed..] {
+        if v.is_empty() {
+            continue;
+        }
+        let name = gm_family_name(dominant_program(v));
+        let next = group_order.len();
+        group_order.entry(name).or_insert(next);
+    }
+    // 안정 정렬: 같은 악기명끼리 모이고, 그룹 순서는 첫 등장(중요도) 순 유지 (리듬 파트는 맨 뒤)
+    voices[fixed..].sort_by_key(|v| {
+        let group = group_order
+            .get(gm_family_name(dominant_program(v)))
+            .copied()
+            .unwrap_or(usize::MAX);
+        (is_rhythm(v), group)
+    });
+
+    let octave_ranges: Vec<OctaveRange> = voices
+        .iter()
+        .enumerate()
+        .map(|(idx, v)| match pinned && idx == 0 {
+            true => options.octave_range_for("멜로디"),
+            false if is_rhythm(v) => options.octave_range_for(RHYTHM_NAME),
+            false => options.octave_range_for(gm_family_name(dominant_program(v))),
+        })
+        .collect();
+
+    let mut family_idx: HashMap<&str, usize> = HashMap::new();
+    let namer = |idx: usize, final_voice: &[Note]| {
+        if pinned && idx == 0 {
+            return "멜로디".to_string();
+        }
+        if is_rhythm(final_voice) {
+            return RHYTHM_NAME.to_string();
+        }
+        let family = gm_family_name(dominant_program(final_voice));
+        let c = family_idx.entry(family).or_insert(0);
+        *c += 1;
+        format!("{}{}", family, c)
+    };
+    build_voices_with_limit(voices, &octave_ranges, source, timing, options, namer)
+}
+
+// 음 높은 순으로 멜로디 + 화음1, 화음2 …
+// mark_bass=true 면 맨 마지막(최저음) 보이스를 "베이스"로 라벨 (2인 모드용)
+// pinned=true 면 첫 보이스는 고정 멜로디라 음높이와 상관없이 맨 앞에 둔다. 리듬 파트는 맨 뒤 "드럼"
+fn name_by_role(
+    mut voices: Vec<Vec<Note>>,
+    source: &[Note],
+    timing: &SongTiming,
+    options: &ConversionOptions,
+    mark_bass: bool,
+    pinned: bool,
+) -> (Vec<Vec<VoiceResult>>, Vec<SimplifyLevel>, Vec<u32>) {
+    // 평균 음높이 높은 순 (멜로디가 맨 앞, 베이스가 맨 뒤, 리듬 파트는 그보다 뒤)
+    voices[pinned as usize..].sort_by_key(|b| (is_rhythm(b), std::cmp::Reverse(avg_pitch(b))));
+
+    let rhythm = voices.last().is_some_and(|v| is_rhythm(v));
+    let count = voices.len();
+    let last = count.saturating_sub(1 + rhythm as usize);
+    let role = |idx: usize| match idx {
+        _ if rhythm && idx + 1 == count => RHYTHM_NAME,
+        0 => "멜로디",
+        _ if mark_bass && idx == last => "베이스",
+        _ => "화음",
+    };
+    let octave_ranges: Vec<OctaveRange> =
+        (0..voices.len()).map(|idx| options.octave_range_for(role(idx))).collect();
+
+    let mut chord_count = 0;
+    let namer = |idx: usize, voice: &[Note]| match role(idx) {
+        _ if is_rhythm(voice) => RHYTHM_NAME.to_string(),
+        "화음" => {
+            chord_count += 1;
+            format!("화음{}", chord_count)
+        }
+        name => name.to_string(),
+    };
+    build_voices_with_limit(voices, &octave_ranges, source, timing, options, namer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::LazyLock;
+
+    fn note(num: u8, start: u32, duration: u32) -> Note {
+        Note {
+            note: num,
+            start,
+            end: start + duration,
+            duration,
+            velocity: 100,
+            volume: 127,
+            program: 0,
+            track: 0,
+            track_name: None,
+            channel: 0,
+            percussion: false,
+        }
+    }
+
+    static FOUR_FOUR: LazyLock<BarMap> = LazyLock::new(BarMap::default);
+
+    // 120 BPM, 템포 변경 없는 4/4 곡
+    fn timing() -> SongTiming<'static> {
+        SongTiming {
+            bpm: 120,
+            tempo_changes: &[],
+            bar_map: &FOUR_FOUR,
+        }
+    }
+
+    fn options(char_limit: usize, split_pages: bool) -> ConversionOptions {
+        ConversionOptions {
+            char_limit,
+            mode: "solo".to_string(),
+            split_pages,
+            sostenuto_pedal: false,
+            dynamics: None,
+            source: SourceFilter::default(),
+            melody_track: None,
+            melody_channel: None,
+            transpose: Transpose::default(),
+            octave_ranges: Vec::new(),
+            balance_voices: false,
+            auto_simplify: false,
+            pitch_bend: PitchBendMode::default(),
+            percussion: None,
+            key: None,
+        }
+    }
+
+    // 8분음표 음계가 반복되는 긴 단선율 (글자수 한도를 여러 번 넘길 만큼)
+    fn long_melody() -> Vec<Note> {
+        let scale = [60u8, 62, 64, 65, 67, 69, 71, 72];
+        (0..400u32)
+            .map(|i| note(scale[i as usize % scale.len()], i * 192 + 96, 192))
+            .collect()
+    }
+
+    // 어림값이 어디에 있든 last_fitting_near 는 이진 탐색(last_fitting)과 같은 값을 찾는다.
+    #[test]
+    fn guessed_search_matches_binary_search() {
+        for last_fit in [0u32, 1, 7, 20, 30, 31] {
+            let fits = |t: u32| t <= last_fit * GRID_SIZE;
+            let grid = |k| k * GRID_SIZE;
+            let expected = last_fitting(1, 30, grid, fits);
+            for guess in 0..=40 {
+                let found = last_fitting_near(1, 30, grid, guess, fits);
+                assert_eq!(found, expected, "맞는 끝 {last_fit}, 어림 {guess}");
+            }
+        }
+    }
+
+    // 칸 나누기: 음을 하나도 버리지 않고, 모든 칸이 글자수 한도 안에 들어와야 한다.
+    #[test]
+    fn split_pages_keeps_every_note_within_limit() {
+        let notes = long_melody();
+        let total = notes.len();
+        let (pages, _, _) = build_voices_with_limit(vec![notes], &[], &[], &timing(), &options(300, true), |_, _| {
+            "멜로디".to_string()
+        });
+
+        assert!(pages.len() > 1, "한도를 넘는 곡인데 칸이 하나뿐: {}", pages.len());
+        let kept: usize = pages.iter().flatten().map(|v| v.note_count).sum();
+        assert_eq!(kept, total, "칸으로 나누면서 음이 빠짐");
+        for (i, page) in pages.iter().enumerate() {
+            for v in page {
+                assert!(v.char_count <= 300, "{}번 칸이 한도 초과: {}", i + 1, v.char_count);
+                assert!(!v.over_limit);
+                assert!(v.content.starts_with("T120"), "칸마다 헤더가 필요: {}", v.content);
+                assert_eq!(v.name, "멜로디");
+            }
+        }
+    }
+
+    // 헤더와 음 하나도 한도를 넘으면 그리드 한 칸씩 전진하며 곡을 다 담되, 그 칸들을 한도 초과로 표시한다.
+    #[test]
+    fn split_pages_mark_pages_over_limit() {
+        let notes: Vec<Note> = (0..4u32).map(|i| note(60 + i as u8, i * 192, 192)).collect();
+        let (pages, _, _) =
+            build_voices_with_limit(vec![notes], &[], &[], &timing(), &options(8, true), |_, _| {
+                "멜로디".to_string()
+            });
+        let kept: usize = pages.iter().flatten().map(|v| v.note_count).sum();
+        assert_eq!(kept, 4, "한도를 넘어도 음은 빠지지 않음");
+        let result = ConversionResult {
+            success: true,
+            pages,
+            ..ConversionResult::failure(ConversionError::NoNotes)
+        };
+        let over = result.over_limit_pages();
+        assert!(!over.is_empty(), "한도 초과 칸이 표시되지 않음");
+        for &i in &over {
+            assert!(result.pages[i].iter().any(|v| v.char_count > 8));
+        }
+    }
+
+    // 칸 경계는 마디 지도의 마디선 위에 온다: 3/4 곡의 마디 첫 박에만 높은 음(C5)을 두면
+    // 모든 칸이 그 음으로 시작하고, 자른 곳은 모두 몇 마디 1박이다.
+    #[test]
+    fn split_pages_cut_on_bar_lines() {
+        use crate::bar_map::TimeSignature;
+        let notes: Vec<Note> = (0..480u32)
+            .map(|i| {
+                let pitch = if i % 6 == 0 { 72 } else { 60 + (i % 6) as u8 };
+                note(pitch, i * 192, 192)
+            })
+            .collect();
+        let bar_map = BarMap::new(vec![TimeSignature { tick: 0, numerator: 3, denominator: 4 }], 1);
+        let three_four = SongTiming { bar_map: &bar_map, ..timing() };
+        let (pages, _, cuts) =
+            build_voices_with_limit(vec![notes], &[], &[], &three_four, &options(280, true), |_, _| {
+                "멜로디".to_string()
+            });
+        assert!(pages.len() > 1);
+        assert_eq!(cuts.len(), pages.len() - 1, "칸 경계마다 자른 시점이 하나씩");
+        let bar_lines = bar_map.bar_lines(0, 192 * 480);
+        for &cut in &cuts {
+            assert!(bar_lines.contains(&cut), "자른 시점이 마디선이 아님: {cuts:?}");
+            assert_eq!(bar_map.bar_beat(cut).1, 1);
+        }
+        for page in &pages {
+            assert!(page[0].content.starts_with("T120V15O5L8C"), "칸 시작이 마디선이 아님: {}", page[0].content);
+        }
+    }
+
+    // 바쁜 보이스(같은 음 32분음표 연타)를 단순화하면, 그 보이스 때문에 잘리던 멜로디가 끝까지 담긴다.
+    #[test]
+    fn balance_simplifies_busiest_voice_before_cropping() {
+        let melody: Vec<Note> = (0..48u32).map(|i| note(72 + (i % 5) as u8, i * 384, 384)).collect();
+        let tremolo: Vec<Note> = (0..48 * 8u32)
+            .map(|i| note(48 + (i / 32 % 3) as u8, i * 48, 48))
+            .collect();
+        let run = |balance_voices: bool| {
+            let options = ConversionOptions {
+                balance_voices,
+                ..options(200, false)
+            };
+            build_voices_with_limit(
+                vec![melody.clone(), tremolo.clone()],
+                &[],
+                &[],
+                &timing(),
+                &options,
+                |i, _| ["멜로디", "화음"][i].to_string(),
+            )
+            .0
+        };
+
+        let cropped = run(false);
+        assert!(cropped[0][0].note_count < melody.len(), "단순화 없이도 다 들어감");
+
+        let balanced = run(true);
+        assert_eq!(balanced[0][0].note_count, melody.len(), "멜로디가 잘림");
+        assert!(balanced[0][1].char_count <= 200);
+        assert_eq!(
+            balanced[0][1].note_count + balanced[0][1].fidelity.simplified_notes,
+            tremolo.len(),
+            "단순화로 빠진 음 집계가 맞지 않음"
+        );
+    }
+
+    // 단순화 사다리: 필요한 단계까지만 오르고, 쓴 단계를 알려 준다.
+    // 격자만 굵게 해도 들어가는 곡은 거기서 멈추고, 안쪽 화음이 빽빽하면 화음 보이스를 뺀다.
+    #[test]
+    fn simplify_ladder_stops_once_song_fits() {
+        let simplify = ConversionOptions {
+            auto_simplify: true,
+            ..options(300, false)
+        };
+        let run = |voices: Vec<Vec<Note>>| {
+            build_voices_with_limit(voices, &[], &[], &timing(), &simplify, |i, _| format!("{}", i))
+        };
+
+        // 64분음표만큼 짧은 4분음표: 격자에 맞추면 깔끔한 4분음표가 된다
+        let ragged: Vec<Note> = (0..100u32).map(|i| note(60 + (i % 3) as u8, i * 384, 360)).collect();
+        let (pages, levels, _) = run(vec![ragged.clone()]);
+        assert_eq!(levels, vec![SimplifyLevel::CoarserGrid]);
+        assert_eq!(pages[0][0].note_count, ragged.len());
+
+        // 멜로디·베이스는 4분음표, 가운데 두 화음 보이스는 음높이가 매번 바뀌는 16분음표
+        let quarters = |pitch: u8| (0..48u32).map(|i| note(pitch, i * 384, 384)).collect();
+        let busy = |base: u8| (0..192u32).map(|i| note(base + (i % 4) as u8, i * 96, 96)).collect();
+        let (pages, levels, _) = run(vec![quarters(84), busy(66), busy(58), quarters(36)]);
+        assert_eq!(levels, SIMPLIFY_LADDER.to_vec());
+        let names: Vec<&str> = pages[0].iter().map(|v| v.name.as_str()).collect();
+        // 옥타브를 오가느라 글자를 더 쓰는 화음(2)부터 빼고, 그걸로 들어가면 멈춘다
+        assert_eq!(names, vec!["0", "1", "3"], "가운데 화음만 빠져야 함");
+        assert_eq!((pages[0][0].note_count, pages[0][2].note_count), (48, 48), "멜로디·베이스가 잘림");
+        // 뺀 화음의 음은 가장 가까운 보이스(화음1)의 단순화 집계로 넘어간다
+        assert_eq!(pages[0][1].fidelity.simplified_notes, 192);
+
+        // 다 들어가는 곡은 손대지 않는다
+        let (_, levels, _) = run(vec![quarters(60)]);
+        assert!(levels.is_empty());
+    }
+
+    // 칸 나누기를 끄면 기존처럼 곡 끝을 잘라 한 칸만 돌려준다.
+    #[test]
+    fn crop_mode_returns_single_page() {
+        let notes = long_melody();
+        let total = notes.len();
+        let (pages, _, cuts) = build_voices_with_limit(vec![notes], &[], &[], &timing(), &options(300, false), |_, _| {
+            "멜로디".to_string()
+        });
+        assert_eq!(pages.len(), 1);
+        assert!(pages[0][0].char_count <= 300);
+        assert!(pages[0][0].note_count < total, "한도를 넘는 곡은 잘려야 함");
+        assert_eq!(cuts.len(), 1, "자른 끝 하나만 알려야 함: {cuts:?}");
+    }
+
+    // 칸 도중의 템포 변경은 다음 칸 헤더 T 로 이어져야 한다.
+    #[test]
+    fn split_pages_carry_tempo_into_header() {
+        let tempos = vec![
+            TempoChange { tick: 0, bpm: 120 },
+            TempoChange { tick: 192 * 100, bpm: 90 },
+        ];
+        let (pages, _, _) = build_voices_with_limit(
+            vec![long_melody()],
+            &[],
+            &[],
+            &SongTiming {
+                bpm: 120,
+                tempo_changes: &tempos,
+                bar_map: &BarMap::default(),
+            },
+            &options(300, true),
+            |_, _| "멜로디".to_string(),
+        );
+        let last = pages.last().unwrap();
+        assert!(last[0].content.starts_with("T90"), "바뀐 템포가 헤더에 없음: {}", last[0].content);
+    }
+
+    // 자른 시점은 박자표로 마디·박을, 템포 변경을 따라 초를 센다.
+    #[test]
+    fn song_position_follows_time_signature_and_tempo() {
+        use crate::bar_map::TimeSignature;
+        let bar_map = BarMap::new(vec![TimeSignature {
+            tick: 0,
+            numerator: 3,
+            denominator: 4,
+        }], 1);
+        let tempos = vec![
+            TempoChange { tick: 0, bpm: 120 },
+            TempoChange { tick: TPB * 12, bpm: 60 },
+        ];
+        // 3/4 여섯 마디(4분음표 18개) 뒤 두 번째 박: 120 BPM 으로 12박(6초) + 60 BPM 으로 7박(7초)
+        let pos = SongPosition::at(TPB * 19, &bar_map, 120, &tempos);
+        assert_eq!((pos.bar, pos.beat), (7, 2));
+        assert!((pos.seconds - 13.0).abs() < 1e-9, "{}", pos.seconds);
+    }
+
+    // 255 BPM 을 넘어 템포를 접은 곡(300 BPM → T150, 틱 ½배)도 자른 곳을 실제 마디·박으로 알린다.
+    // 300 BPM 4/4 는 한 마디가 0.8초라, 마디선에서 자른 곳의 마디 번호는 초로도 셀 수 있다.
+    #[test]
+    fn cuts_of_folded_song_report_real_bars() {
+        use midly::{Format, Header, MetaMessage, MidiMessage, Smf, Timing, TrackEvent, TrackEventKind};
+
+        let meta = |message: MetaMessage<'static>| TrackEvent {
+            delta: 0.into(),
+            kind: TrackEventKind::Meta(message),
+        };
+        let event = |delta: u32, message: MidiMessage| TrackEvent {
+            delta: delta.into(),
+            kind: TrackEventKind::Midi { channel: 0.into(), message },
+        };
+        let mut track = vec![
+            meta(MetaMessage::Tempo(200_000.into())),
+            meta(MetaMessage::TimeSignature(4, 2, 24, 8)),
+        ];
+        let scale = [60u8, 62, 64, 65, 67, 69, 71, 72];
+        for i in 0..1024 {
+            let key = scale[i % scale.len()].into();
+            track.push(event(0, MidiMessage::NoteOn { key, vel: 100.into() }));
+            track.push(event(240, MidiMessage::NoteOff { key, vel: 0.into() }));
+        }
+        track.push(meta(MetaMessage::EndOfTrack));
+        let mut smf = Smf::new(Header::new(Format::SingleTrack, Timing::Metrical(480.into())));
+        smf.tracks.push(track);
+        let mut bytes = Vec::new();
+        smf.write_std(&mut bytes).unwrap();
+
+        let result = convert(&bytes, &options(300, true)).unwrap();
+        assert_eq!(result.bpm, 150);
+        assert!(!result.cuts.is_empty(), "한도를 넘는 곡인데 자른 곳이 없음");
+        for cut in &result.cuts {
+            let bar = (cut.seconds / 0.8).round() as u32 + 1;
+            assert_eq!((cut.bar, cut.beat), (bar, 1), "{cut:?}");
+        }
+    }
+
+    // 충실도: 3보이스에 4음 화음 → 1음 버려짐, 뒤에 든 내성부 음이 앞 음 꼬리를 자름.
+    // 깨끗한 길이만 쓰므로 근사·드리프트는 0 이어야 한다.
+    #[test]
+    fn fidelity_counts_dropped_and_truncated_notes() {
+        let notes = vec![
+            note(72, 0, 768),
+            note(64, 0, 768),
+            note(60, 0, 768),
+            note(48, 0, 768),
+            note(67, 192, 192),
+        ];
+        let total = notes.len();
+        let pages = convert_voices(notes, &timing(), &options(300, false)).pages;
+        let page = &pages[0];
+
+        let kept: usize = page.iter().map(|v| v.note_count).sum();
+        let dropped: usize = page.iter().map(|v| v.fidelity.dropped_notes).sum();
+        let truncated: usize = page.iter().map(|v| v.fidelity.truncated_notes).sum();
+        assert_eq!(kept + dropped, total, "버려진 음 수가 맞지 않음: {:?}", page);
+        assert_eq!(dropped, 1);
+        assert_eq!(truncated, 1);
+        for v in page {
+            assert_eq!(v.fidelity.approximated_notes, 0, "{}", v.content);
+            assert_eq!(v.fidelity.max_drift_ticks, 0, "{}", v.content);
+            assert_eq!(v.fidelity.unmatched_notes, 0, "{}", v.content);
+        }
+    }
+
+    // 다시 읽은 MML 에서 음 하나가 타이로 합쳐지거나 빠져도 뒤의 음은 제 짝(같은 높이, 가장 가까운 시작)과 맞댄다.
+    #[test]
+    fn round_trip_matches_by_pitch_and_nearest_start() {
+        let notes = vec![note(60, 0, 192), note(60, 192, 192), note(62, 384, 192), note(64, 576, 192)];
+        let merged = round_trip_fidelity(&notes, "T120O4L8C&CDE");
+        assert_eq!(merged.approximated_notes, 1, "합쳐진 C 하나만 길이가 다름");
+        assert_eq!(merged.max_drift_ticks, 0);
+        assert_eq!(merged.unmatched_notes, 1);
+
+        let missing = round_trip_fidelity(&notes, "T120O4L8CCRE");
+        assert_eq!((missing.approximated_notes, missing.max_drift_ticks), (0, 0));
+        assert_eq!(missing.unmatched_notes, 1, "빠진 D");
+
+        let extra = round_trip_fidelity(&notes[..1], "T120O4L8CD");
+        assert_eq!(extra.unmatched_notes, 1, "MML 에만 있는 D");
+    }
+
+    // 두 트랙이 같은 음을 겹쳐 칠 때 하나만 담기면 나머지 하나는 버린 음으로 센다.
+    #[test]
+    fn doubled_unison_parts_are_not_merged_in_accounting() {
+        let lead = note(72, 0, 384);
+        let double = Note { track: 1, channel: 1, ..lead.clone() };
+        let voices = vec![vec![lead.clone()]];
+        let source = [lead, double];
+        let dropped = attribute_dropped_notes(&voices, &source);
+        assert_eq!(dropped[0].len(), 1);
+        assert_eq!(dropped[0][0].track, 1);
+    }
+
+    // 트랙·채널 선택은 옵션 JSON 최상위 필드로 받고, 없으면 전부 변환한다.
+    #[test]
+    fn source_filter_is_read_from_flat_options() {
+        let plain: ConversionOptions =
+            serde_json::from_str(r#"{"char_limit": 2400, "mode": "solo"}"#).unwrap();
+        assert_eq!(plain.source, SourceFilter::default());
+
+        let picked: ConversionOptions = serde_json::from_str(
+            r#"{"char_limit": 2400, "mode": "solo", "exclude_tracks": [2], "include_channels": [0]}"#,
+        )
+        .unwrap();
+        assert_eq!(picked.source.exclude_tracks, vec![2]);
+        assert_eq!(picked.extract_options().source.include_channels, vec![0]);
+    }
+
+    #[test]
+    fn list_tracks_groups_notes_by_track() {
+        let named = |n: Note, track: usize, name: &str, channel: u8| Note {
+            track,
+            track_name: Some(name.into()),
+            channel,
+            ..n
+        };
+        let notes = vec![
+            named(note(60, 0, 96), 2, "Pad", 3),
+            named(note(72, 0, 96), 1, "Vocal", 0),
+            named(note(64, 96, 96), 2, "Pad", 1),
+        ];
+        let tracks = list_tracks(&notes);
+        let summary: Vec<(usize, Option<&str>, &[u8], usize)> = tracks
+            .iter()
+            .map(|t| (t.index, t.name.as_deref(), t.channels.as_slice(), t.note_count))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![(1, Some("Vocal"), &[0u8][..], 1), (2, Some("Pad"), &[1u8, 3][..], 2)]
+        );
+    }
+
+    // 현악 패드가 보컬보다 높아도, 보컬 트랙을 멜로디로 고정하면 첫 보이스("멜로디")는 보컬만 담는다.
+    #[test]
+    fn pinned_melody_track_stays_in_first_voice() {
+        let on_track = |n: Note, track: usize, program: u8| Note { track, program, ..n };
+        let notes = vec![
+            on_track(note(84, 0, 1152), 2, 48), // 높은 현악 패드
+            on_track(note(67, 0, 384), 1, 0), // 보컬 멜로디
+            on_track(note(69, 384, 384), 1, 0),
+            on_track(note(71, 768, 384), 1, 0),
+            on_track(note(48, 0, 1152), 3, 32), // 베이스
+        ];
+
+        let unpinned = convert_voices(notes.clone(), &timing(), &options(2400, false)).pages;
+        assert_ne!(unpinned[0][0].note_count, 3, "고정하지 않으면 패드가 멜로디로 잡힘");
+
+        for mode in ["solo", "duo", "ensemble"] {
+            let pinned = ConversionOptions {
+                mode: mode.to_string(),
+                melody_track: Some(1),
+                ..options(2400, false)
+            };
+            let pages = convert_voices(notes.clone(), &timing(), &pinned).pages;
+            let first = &pages[0][0];
+            assert_eq!(first.name, "멜로디", "{mode}");
+            assert_eq!(first.note_count, 3, "{mode}: {}", first.content);
+            assert!(!first.content.contains('C'), "{mode}: 패드 음이 섞임 {}", first.content);
+            let kept: usize = pages[0].iter().map(|v| v.note_count).sum();
+            assert_eq!(kept, notes.len(), "{mode}: 남은 보이스에 나머지 음이 다 담겨야 함");
+        }
+    }
+
+    // 리듬 파트를 켜면 드럼 음이 맨 뒤 "드럼" 보이스 하나로 모이고, 끄면 빠진다. 멜로디 보이스에는 섞이지 않는다.
+    #[test]
+    fn percussion_becomes_rhythm_voice() {
+        let drum = |key: u8, start: u32| Note { channel: 9, percussion: true, ..note(key, start, 48) };
+        let notes = vec![
+            note(72, 0, 768),
+            note(48, 0, 768),
+            drum(36, 0),
+            drum(42, 0),
+            drum(38, 384),
+            drum(42, 576),
+        ];
+
+        for mode in ["solo", "ensemble"] {
+            let with_drums = ConversionOptions {
+                mode: mode.to_string(),
+                percussion: Some(PercussionPitches::default()),
+                ..options(2400, false)
+            };
+            let voices = &convert_voices(notes.clone(), &timing(), &with_drums).pages[0];
+            let rhythm = voices.last().unwrap();
+            assert_eq!(rhythm.name, "드럼", "{mode}");
+            assert_eq!(rhythm.note_count, 3, "{mode}: 같은 때 친 킥·하이햇은 하나로");
+            let melodic: usize = voices[..voices.len() - 1].iter().map(|v| v.note_count).sum();
+            assert_eq!(melodic, 2, "{mode}");
+        }
+
+        let plain = convert_voices(notes, &timing(), &options(2400, false)).pages;
+        assert!(plain[0].iter().all(|v| v.name != "드럼"));
+    }
+
+    // 같은 때 친 킥·하이햇 중 빠진 하이햇과 리듬 파트에 없는 탐은 리듬 파트의 버린 음으로 세어,
+    // 다 담긴 곡이 잘린 곡으로 보이지 않는다.
+    #[test]
+    fn merged_and_unmapped_drums_are_not_truncation() {
+        let drum = |key: u8, start: u32| Note { channel: 9, percussion: true, ..note(key, start, 48) };
+        let mut notes: Vec<Note> = (0..8u32).map(|i| note(72, i * 384, 384)).collect();
+        for i in 0..8u32 {
+            notes.extend([drum(36, i * 384), drum(42, i * 384)]);
+        }
+        notes.push(drum(45, 192)); // 탐
+        let with_drums = ConversionOptions {
+            mode: "ensemble".to_string(),
+            percussion: Some(PercussionPitches::default()),
+            ..options(2400, false)
+        };
+        let total_notes = notes.len();
+        let pages = convert_voices(notes, &timing(), &with_drums).pages;
+        let rhythm = pages[0].last().unwrap();
+        assert_eq!((rhythm.note_count, rhythm.fidelity.dropped_notes), (8, 9));
+        let result = ConversionResult {
+            success: true,
+            pages,
+            total_notes,
+            ..ConversionResult::failure(ConversionError::NoNotes)
+        };
+        assert!(!result.is_truncated());
+    }
+
+    // 자동 조옮김: 옥타브 범위(O1~O8) 밖 음을 먼저 없애고, 그다음 옥타브 이동 글자를 줄인다.
+    #[test]
+    fn auto_transpose_avoids_clamped_notes_and_octave_jumps() {
+        // MIDI 12~19 는 O0 이라 그대로면 O1 로 클램프 → 한 옥타브 올려야 제 음
+        let low = vec![vec![note(12, 0, 384), note(16, 384, 384), note(19, 768, 384)]];
+        assert_eq!(auto_transpose(&low), 12);
+
+        // B3·C4 를 오가는 선율은 음마다 옥타브가 바뀐다 → 반음 옮겨 한 옥타브 안으로
+        let seesaw: Vec<Note> = (0..8u32).map(|i| note(59 + (i % 2) as u8, i * 192, 192)).collect();
+        let shift = auto_transpose(std::slice::from_ref(&seesaw));
+        assert_eq!(shift.abs(), 1);
+
+        // 변환 결과에는 고른 값이 실린다
+        let auto = ConversionOptions {
+            transpose: Transpose::Auto,
+            ..options(2400, false)
+        };
+        let applied = convert_voices(low.concat(), &timing(), &auto).transpose;
+        assert_eq!(applied, 12);
+
+        // 이미 범위 안이고 옥타브 이동도 없으면 옮기지 않는다
+        let plain = vec![vec![note(60, 0, 384), note(64, 384, 384)]];
+        assert_eq!(auto_transpose(&plain), 0);
+    }
+
+    #[test]
+    fn fixed_transpose_shifts_every_note() {
+        let up = ConversionOptions {
+            transpose: Transpose::Semitones(2),
+            ..options(2400, false)
+        };
+        let VoiceLayout {
+            pages,
+            transpose: applied,
+            ..
+        } = convert_voices(vec![note(60, 0, 384)], &timing(), &up);
+        assert_eq!(applied, 2);
+        assert!(pages[0][0].content.ends_with('D'), "{}", pages[0][0].content);
+    }
+
+    // JSON 으로는 반음 수 또는 "auto", 없으면 옮기지 않는다.
+    #[test]
+    fn transpose_option_accepts_number_or_auto() {
+        let parse = |json: &str| serde_json::from_str::<ConversionOptions>(json).map(|o| o.transpose);
+        let base = r#""char_limit": 2400, "mode": "solo""#;
+        assert_eq!(parse(&format!("{{{base}}}")).unwrap(), Transpose::Semitones(0));
+        assert_eq!(parse(&format!(r#"{{{base}, "transpose": -3}}"#)).unwrap(), Transpose::Semitones(-3));
+        assert_eq!(parse(&format!(r#"{{{base}, "transpose": "auto"}}"#)).unwrap(), Transpose::Auto);
+        assert!(parse(&format!(r#"{{{base}, "transpose": "up"}}"#)).is_err());
+        assert_eq!(serde_json::to_value(Transpose::Auto).unwrap(), "auto");
+    }
+
+    // 보이스별 옥타브 범위: 2인 모드 베이스를 O1~O2 로 두면 E3(52) 는 E2(40) 로 접혀 적히고,
+    // 접은 음도 원본 음으로 집계되어 버려진 음으로 잡히지 않는다.
+    #[test]
+    fn octave_range_folds_voice_notes() {
+        let notes = vec![
+            note(52, 0, 384),
+            note(64, 0, 384),
+            note(67, 0, 384),
+            note(72, 0, 384),
+            note(52, 384, 384),
+            note(76, 384, 384),
+        ];
+        let duo = ConversionOptions {
+            mode: "duo".to_string(),
+            octave_ranges: vec![VoiceOctaveRange {
+                voice: "베이스".to_string(),
+                range: OctaveRange { min: 1, max: 2 },
+            }],
+            ..options(2400, false)
+        };
+        let pages = convert_voices(notes, &timing(), &duo).pages;
+        let bass = pages[0].iter().find(|v| v.name == "베이스").expect("베이스 보이스");
+        assert!(bass.content.contains("O2"), "{}", bass.content);
+        let parsed = parse_mml(&bass.content).unwrap();
+        assert!(parsed.notes.iter().all(|n| n.note == 40), "{}", bass.content);
+        assert!(pages[0].iter().all(|v| v.fidelity.dropped_notes == 0), "{:?}", pages[0]);
+
+        let json = serde_json::to_value(&duo.octave_ranges[0]).unwrap();
+        assert_eq!(json, serde_json::json!({"voice": "베이스", "min": 1, "max": 2}));
+    }
+}
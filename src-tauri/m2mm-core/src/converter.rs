@@ -0,0 +1,2353 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use crate::bar_map::{BarMap, TimeSignature};
+use crate::drum_parts::DrumParts;
+use crate::error::ConversionError;
+use crate::key::Key;
+use crate::mml_optimizer::optimize_mml_with_offsets;
+use crate::utils::mml::midi_to_note_name;
+
+/// 표준 TPB (4분음표당 틱 수). 추출한 음은 모두 이 해상도로 옮겨진다.
+pub const TPB: u32 = 384;
+/// 기본 양자화 그리드 (64분음표)
+pub const GRID_SIZE: u32 = 24;
+/// 셋잇단 그리드 (48분음표 = 셋잇단 32분음표). 셋잇단 느낌으로 판정된 박은 이 그리드로 스냅한다.
+pub const TRIPLET_GRID: u32 = 32;
+
+// MML 유효 옥타브 범위 (이 밖으로 나가면 O0/O-1/O9 같은 잘못된 토큰이 되어 깨짐)
+pub(crate) const MML_OCTAVE_MIN: i32 = 1;
+pub(crate) const MML_OCTAVE_MAX: i32 = 8;
+
+// MML T(템포) 명령 유효 범위. 이 상한을 넘는 빠른 곡은 템포 폴딩으로 접는다.
+const MML_TEMPO_MIN: u32 = 32;
+const MML_TEMPO_MAX: u32 = 255;
+
+// 매칭 실패 시 기본 길이 (16분음표 = 96틱)
+const FALLBACK_LENGTH: &str = "16";
+const FALLBACK_TICKS: u32 = 96;
+
+// SMPTE 타임코드 파일에 붙이는 가상 템포. 틱이 박이 아니라 실제 시간(프레임)이라
+// 이 템포의 4분음표 길이를 한 박으로 삼아 TPB 그리드에 옮긴다.
+const SMPTE_DERIVED_BPM: f64 = 120.0;
+
+// 드럼 채널 (General MIDI) - 리듬 파트를 만들 때가 아니면 변환에서 제외.
+// GS/XG 파일이 다른 채널에 둔 드럼 세트는 [`DrumParts`] 가 찾는다.
+pub(crate) const DRUM_CHANNEL: u8 = 9;
+
+// 채널 음량 컨트롤 체인지 번호 (볼륨 × 익스프레션 = 채널 음량)
+const CC_VOLUME: u8 = 7;
+const CC_EXPRESSION: u8 = 11;
+
+// MML 음량(V) 최대값
+const MML_VOLUME_MAX: u32 = 15;
+
+// 페달 컨트롤 체인지 번호 (값 64 이상 = 밟음)
+const CC_SUSTAIN: u8 = 64;
+const CC_SOSTENUTO: u8 = 66;
+const PEDAL_ON_THRESHOLD: u8 = 64;
+
+// 피치 벤드 범위를 정하는 RPN 0 (CC101/CC100 으로 고르고 CC6 반음·CC38 센트로 적는다)
+const CC_RPN_MSB: u8 = 101;
+const CC_RPN_LSB: u8 = 100;
+const CC_DATA_ENTRY_MSB: u8 = 6;
+const CC_DATA_ENTRY_LSB: u8 = 38;
+const RPN_NULL: (u8, u8) = (127, 127);
+// 벤드 범위 기본값 (GM, ±2반음)
+const DEFAULT_BEND_RANGE: f64 = 2.0;
+
+/// 추출·양자화가 끝난 음 하나 (틱 단위는 [`TPB`])
+#[derive(Debug, Clone)]
+pub struct Note {
+    pub note: u8,
+    pub start: u32,
+    pub end: u32,
+    pub duration: u32,
+    pub velocity: u8,
+    pub volume: u8,  // 시작 시점 채널 볼륨(CC7) × 익스프레션(CC11), 127 = 최대
+    pub program: u8, // GM 악기 번호 (0~127)
+    pub track: usize, // SMF 안 트랙 순서 (0부터)
+    pub track_name: Option<Arc<str>>, // 트랙 이름 메타(FF 03). 같은 트랙 음끼리 공유
+    pub channel: u8, // MIDI 채널 (0부터, 드럼 = 9)
+    pub percussion: bool, // 드럼 파트 음 (리듬 파트를 만들 때만 뽑는다)
+}
+
+/// 템포 변경 (tick 시점부터 bpm)
+#[derive(Debug, Clone)]
+pub struct TempoChange {
+    pub tick: u32,
+    pub bpm: u32,
+}
+
+/// MIDI 음표 추출 옵션
+#[derive(Debug, Clone, Default)]
+pub struct ExtractOptions {
+    /// 소스테누토 페달(CC66)도 반영한다. 밟는 순간 눌려 있던 음만 페달을 뗄 때까지 붙잡는다.
+    /// (서스테인 페달 CC64 는 항상 반영)
+    pub sostenuto: bool,
+    /// 변환에 넣을 트랙·채널 (기본: 전부)
+    pub source: SourceFilter,
+    /// 피치 벤드(와 RPN 0 벤드 범위) 반영 방식 (기본: 무시)
+    pub pitch_bend: PitchBendMode,
+    /// 드럼 채널 음도 뽑는다 ([`Note::percussion`]). 리듬 파트용 (기본: 버림)
+    pub percussion: bool,
+}
+
+/// 피치 벤드로 음높이가 바뀐 음을 적는 방식. 벤드는 가장 가까운 반음으로 반올림해 본다.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PitchBendMode {
+    /// 벤드를 무시하고 건반 음높이 그대로
+    #[default]
+    Ignore,
+    /// 음이 가장 오래 머문 음높이 하나로 옮긴다 (밴딩해 올린 기타 음 등)
+    Sustain,
+    /// 반음을 넘나드는 벤드는 반음 계단의 짧은 음들로 나눈다 (신스 글라이드 등)
+    Steps,
+}
+
+/// 변환에 넣을 트랙·채널 선택. 트랙은 SMF 안 순서, 채널은 0부터(드럼 = 9) 센다.
+/// include 목록이 비어 있으면 전부 넣고, exclude 목록은 그 뒤에 다시 뺀다.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SourceFilter {
+    pub include_tracks: Vec<usize>,
+    pub exclude_tracks: Vec<usize>,
+    pub include_channels: Vec<u8>,
+    pub exclude_channels: Vec<u8>,
+}
+
+impl SourceFilter {
+    /// track 트랙의 channel 채널 음을 변환에 넣는지
+    pub fn accepts(&self, track: usize, channel: u8) -> bool {
+        (self.include_tracks.is_empty() || self.include_tracks.contains(&track))
+            && !self.exclude_tracks.contains(&track)
+            && (self.include_channels.is_empty() || self.include_channels.contains(&channel))
+            && !self.exclude_channels.contains(&channel)
+    }
+}
+
+/// MML 생성 옵션
+#[derive(Debug, Clone, Default)]
+pub struct MmlOptions {
+    /// 셈여림(벨로시티 × 채널 음량)을 V0~V15 로 반영한다. 값은 히스테리시스(V 단계 수):
+    /// 직전에 적은 V 와 이만큼 이상 차이 날 때만 V 를 새로 적어 글자수를 아낀다.
+    /// None 이면 헤더 V15 고정.
+    pub dynamics: Option<u8>,
+    /// 임시표를 플랫(-)으로 적는다 (플랫 조의 곡, [`Key::uses_flats`]). 기본은 샵(+).
+    pub flats: bool,
+}
+
+// 건반을 누른 뒤 아직 Note 로 확정되지 않은 음
+struct HeldNote {
+    start: u32,
+    velocity: u8,
+    volume: u8,
+    program: u8,
+    track: usize,
+    track_name: Option<Arc<str>>,
+    channel: u8,
+    percussion: bool,
+    released: bool,  // 건반은 뗐지만 페달이 붙잡고 있음
+    sostenuto: bool, // 소스테누토 페달을 밟는 순간 눌려 있던 음
+    bends: Vec<(u32, i8)>, // 벤드가 바뀐 (원본 틱, 반올림한 반음). 첫 항목은 음 시작
+}
+
+// 점음표 포함 정확한 길이 매핑
+pub(crate) fn get_exact_lengths() -> HashMap<u32, &'static str> {
+    let mut map = HashMap::new();
+
+    // 정확도 모드: 점음표 포함
+    map.insert(2304, "1.");
+    map.insert(1536, "1");
+    map.insert(1152, "2.");
+    map.insert(768, "2");
+    map.insert(576, "4.");
+    map.insert(384, "4");
+    map.insert(288, "8.");
+    map.insert(192, "8");
+    map.insert(144, "16.");
+    map.insert(96, "16");
+    map.insert(72, "32.");
+    map.insert(48, "32");
+    map.insert(36, "64.");
+    map.insert(24, "64");
+
+    // 셋잇단 길이: 온음표를 3·6·12·24·48 등분 (셋잇단 2분·4분·8분·16분·32분)
+    map.insert(512, "3");
+    map.insert(256, "6");
+    map.insert(128, "12");
+    map.insert(64, "24");
+    map.insert(32, "48");
+
+    map
+}
+
+// 박(4분음표)마다 음 시작점을 일반 그리드(24틱)와 셋잇단 그리드(32틱)에 맞춰 보고,
+// 셋잇단 쪽 오차 합이 더 작은 박을 셋잇단 박으로 판정한다. (동점이면 일반 그리드)
+// 모든 트랙의 시작점을 함께 보므로 한 박 안에서는 모든 보이스가 같은 그리드를 쓴다.
+fn detect_triplet_beats(onsets: impl Iterator<Item = u32>) -> HashSet<u32> {
+    let snap_error = |offset: u32, grid: u32| {
+        let snapped = ((offset as f32 / grid as f32).round() as u32) * grid;
+        snapped.abs_diff(offset)
+    };
+    let mut errors: HashMap<u32, (u32, u32)> = HashMap::new(); // 박 → (일반 오차, 셋잇단 오차)
+    for tick in onsets {
+        let offset = tick % TPB;
+        let e = errors.entry(tick / TPB).or_default();
+        e.0 += snap_error(offset, GRID_SIZE);
+        e.1 += snap_error(offset, TRIPLET_GRID);
+    }
+    errors
+        .into_iter()
+        .filter(|&(_, (straight, triplet))| triplet < straight)
+        .map(|(beat, _)| beat)
+        .collect()
+}
+
+// tick 이 속한 박의 그리드 간격
+fn grid_step(tick: u32, triplet_beats: &HashSet<u32>) -> u32 {
+    if triplet_beats.contains(&(tick / TPB)) {
+        TRIPLET_GRID
+    } else {
+        GRID_SIZE
+    }
+}
+
+// tick 이 속한 박의 그리드(일반/셋잇단)로 스냅. 박 경계는 두 그리드 모두 위에 있다.
+fn snap_to_grid(tick: u32, triplet_beats: &HashSet<u32>) -> u32 {
+    let grid = grid_step(tick, triplet_beats);
+    let beat_start = tick / TPB * TPB;
+    beat_start + ((((tick - beat_start) as f32) / grid as f32).round() as u32) * grid
+}
+
+fn gcd(a: u32, b: u32) -> u32 {
+    if b == 0 { a } else { gcd(b, a % b) }
+}
+
+// ticks 와 같은 계열의 길이만 내림차순으로 고른다.
+// 일반 그리드(24틱)로 떨어지면 일반 길이만, 셋잇단 그리드(32틱)로 떨어지면 32틱 배수 길이만,
+// 둘이 섞인 길이(셋잇단 박 ↔ 일반 박에 걸친 음·쉼표)면 전부 쓴다.
+fn family_lengths(ticks: u32, exact_lengths: &HashMap<u32, &str>) -> Vec<u32> {
+    let mut lengths: Vec<u32> = exact_lengths
+        .keys()
+        .copied()
+        .filter(|&l| {
+            if ticks.is_multiple_of(GRID_SIZE) {
+                l.is_multiple_of(GRID_SIZE / 2) // 셋잇단 길이 제외 (점64분 36틱까지 일반 길이)
+            } else if ticks.is_multiple_of(TRIPLET_GRID) {
+                l.is_multiple_of(TRIPLET_GRID)
+            } else {
+                true
+            }
+        })
+        .collect();
+    lengths.sort_by(|a, b| b.cmp(a));
+    lengths
+}
+
+// lengths 로 ticks 를 정확히 채우는 최소 개수 분할 (긴 길이 먼저). 채울 수 없으면 None.
+// 일반·셋잇단이 섞인 길이는 탐욕 분할이 8·16틱 같은 자투리를 남길 수 있어 이걸로 푼다.
+fn min_length_split(ticks: u32, lengths: &[u32]) -> Option<Vec<u32>> {
+    let unit = lengths.iter().fold(0, |g, &l| gcd(g, l));
+    if unit == 0 || !ticks.is_multiple_of(unit) {
+        return None;
+    }
+    let n = (ticks / unit) as usize;
+    // best[i] = i*unit 틱을 채우는 최소 개수, pick[i] = 그때 마지막으로 쓴 길이
+    let mut best = vec![usize::MAX; n + 1];
+    let mut pick = vec![0u32; n + 1];
+    best[0] = 0;
+    for i in 1..=n {
+        for &l in lengths {
+            let k = (l / unit) as usize;
+            if k <= i && best[i - k] != usize::MAX && best[i - k] + 1 < best[i] {
+                best[i] = best[i - k] + 1;
+                pick[i] = l;
+            }
+        }
+    }
+    if best[n] == usize::MAX {
+        return None;
+    }
+    let mut split = Vec::with_capacity(best[n]);
+    let mut i = n;
+    while i > 0 {
+        split.push(pick[i]);
+        i -= (pick[i] / unit) as usize;
+    }
+    split.sort_by(|a, b| b.cmp(a));
+    Some(split)
+}
+
+// 남은 틱을 길이 토큰(쉼표 포함)으로 정확히 적을 수 있는가
+fn is_representable(ticks: u32, lengths: &[u32]) -> bool {
+    ticks.is_multiple_of(GRID_SIZE)
+        || ticks.is_multiple_of(TRIPLET_GRID)
+        || min_length_split(ticks, lengths).is_some()
+}
+
+// 정확히 매칭되는 길이 찾기 (점음표 포함)
+fn find_exact_match(ticks: u32, exact_lengths: &HashMap<u32, &str>) -> Option<Vec<(String, u32)>> {
+    exact_lengths.get(&ticks).map(|&s| vec![(s.to_string(), ticks)])
+}
+
+// 타이 조합 찾기
+fn find_tie_combination(
+    ticks: u32,
+    max_ties: Option<usize>,
+    exact_lengths: &HashMap<u32, &str>,
+) -> Vec<(String, u32)> {
+    let mut result = Vec::new();
+    let mut remaining = ticks;
+    let mut tie_count = 0;
+
+    let lengths = family_lengths(ticks, exact_lengths);
+
+    for &length_ticks in &lengths {
+        if let Some(max) = max_ties && tie_count >= max {
+            break;
+        }
+
+        while remaining >= length_ticks {
+            if let Some(&length_str) = exact_lengths.get(&length_ticks) {
+                result.push((length_str.to_string(), length_ticks));
+                remaining -= length_ticks;
+                tie_count += 1;
+
+                if let Some(max) = max_ties && tie_count >= max {
+                    break;
+                }
+            } else {
+                break;
+            }
+        }
+    }
+
+    // 탐욕 분할이 어떤 길이로도 못 적는 자투리를 남기면(섞인 계열) 최소 개수 분할로 다시 나눈다.
+    // 타이 상한이 있으면 앞쪽(긴 길이)만 쓰고, 버린 뒤쪽은 적을 수 있는 길이라 쉼표로 흡수된다.
+    if !is_representable(remaining, &lengths)
+        && let Some(split) = min_length_split(ticks, &lengths)
+    {
+        result = split
+            .into_iter()
+            .take(max_ties.unwrap_or(usize::MAX))
+            .map(|t| (exact_lengths[&t].to_string(), t))
+            .collect();
+    }
+
+    if result.is_empty() {
+        result.push((FALLBACK_LENGTH.to_string(), FALLBACK_TICKS));
+    }
+
+    result
+}
+
+// 안전한 근사치 찾기 (타이 없이 단일 길이)
+// 원래 길이를 절대 넘지 않는 가장 큰 길이를 고른다.
+// → 위로 반올림하지 않으므로 타이밍이 밀리지 않고(드리프트 0), 값 기준 max라 동점이 없어 결정적이다.
+//   모자란 만큼은 다음 음 앞 쉼표로 흡수되어 전체 동기화는 유지된다.
+//   (같은 계열 길이만 쓰고, 모자란 만큼이 쉼표로 적을 수 있는 길이일 때만 고른다)
+fn find_safe_approximation(ticks: u32, exact_lengths: &HashMap<u32, &str>) -> Vec<(String, u32)> {
+    let lengths = family_lengths(ticks, exact_lengths);
+    match lengths
+        .iter()
+        .copied()
+        .find(|&l| l <= ticks && is_representable(ticks - l, &lengths))
+    {
+        Some(t) => vec![(exact_lengths[&t].to_string(), t)],
+        None => vec![(FALLBACK_LENGTH.to_string(), FALLBACK_TICKS)],
+    }
+}
+
+// 옥타브별 최적 길이 찾기 (음 씹힘 방지 우선)
+fn find_best_length(ticks: u32, octave: i32, exact_lengths: &HashMap<u32, &str>) -> Vec<(String, u32)> {
+    // 1. 정확한 매칭 (점음표 포함)
+    if let Some(exact) = find_exact_match(ticks, exact_lengths) {
+        return exact;
+    }
+
+    // 2. 옥타브별 전략 (음 씹힘 방지)
+    if octave <= 4 {
+        // 중저음: 타이 자유롭게
+        find_tie_combination(ticks, None, exact_lengths)
+    } else if octave == 5 {
+        // 고음: 타이 2개까지만 (음 씹힘 방지)
+        let ties = find_tie_combination(ticks, Some(2), exact_lengths);
+        if ties.len() <= 2 {
+            ties
+        } else {
+            find_safe_approximation(ticks, exact_lengths)
+        }
+    } else {
+        // 초고음: 근사치만
+        find_safe_approximation(ticks, exact_lengths)
+    }
+}
+
+// note-on/note-off 쌍으로부터 TPB 변환만 적용한 Note 생성 (그리드 스냅은 셋잇단 판정 뒤에)
+fn build_note(note_num: u8, held: &HeldNote, start: u32, end_tick: u32, tpb_ratio: f64) -> Note {
+    let duration = end_tick.saturating_sub(start);
+
+    // TPB 변환
+    let start_converted = (start as f64 * tpb_ratio).round() as u32;
+    let duration_converted = (duration as f64 * tpb_ratio).round() as u32;
+
+    Note {
+        note: note_num,
+        start: start_converted,
+        end: start_converted + duration_converted,
+        duration: duration_converted,
+        velocity: held.velocity,
+        volume: held.volume,
+        program: held.program,
+        track: held.track,
+        track_name: held.track_name.clone(),
+        channel: held.channel,
+        percussion: held.percussion,
+    }
+}
+
+// 다 울린 음을 Note 로. 벤드 반영 방식에 따라 머문 음높이로 옮기거나 반음 계단으로 나눈다.
+fn finish_note(
+    note_num: u8,
+    held: &HeldNote,
+    end_tick: u32,
+    tpb_ratio: f64,
+    mode: PitchBendMode,
+) -> Vec<Note> {
+    let bent = |offset: i8| (note_num as i32 + offset as i32).clamp(0, 127) as u8;
+    // 같은 벤드 반음으로 머문 구간들 (원본 틱)
+    let mut spans: Vec<(u32, u32, i8)> = Vec::new();
+    for (i, &(tick, offset)) in held.bends.iter().enumerate() {
+        let next = held.bends.get(i + 1).map_or(end_tick, |&(t, _)| t.min(end_tick));
+        if next <= tick {
+            continue;
+        }
+        match spans.last_mut() {
+            Some(last) if last.2 == offset => last.1 = next,
+            _ => spans.push((tick, next, offset)),
+        }
+    }
+    if spans.is_empty() {
+        return vec![build_note(note_num, held, held.start, end_tick, tpb_ratio)];
+    }
+
+    match mode {
+        PitchBendMode::Ignore => vec![build_note(note_num, held, held.start, end_tick, tpb_ratio)],
+        PitchBendMode::Sustain => {
+            let mut stays: Vec<(i8, u32)> = Vec::new();
+            for &(from, to, offset) in &spans {
+                match stays.iter_mut().find(|(o, _)| *o == offset) {
+                    Some((_, ticks)) => *ticks += to - from,
+                    None => stays.push((offset, to - from)),
+                }
+            }
+            // 가장 오래 머문 음높이. 같으면 건반 음에 가까운 쪽
+            let (offset, _) = stays
+                .into_iter()
+                .max_by_key(|&(o, ticks)| (ticks, std::cmp::Reverse(o.unsigned_abs())))
+                .unwrap_or((0, 0));
+            vec![build_note(bent(offset), held, held.start, end_tick, tpb_ratio)]
+        }
+        PitchBendMode::Steps => {
+            // 격자 한 칸보다 짧게 지나간 반음은 다음 계단에 합친다 (마지막이면 앞 계단에)
+            let short = |from: u32, to: u32| ((to - from) as f64 * tpb_ratio) < GRID_SIZE as f64;
+            let mut steps: Vec<(u32, u32, i8)> = Vec::new();
+            let mut pending_start: Option<u32> = None;
+            for (i, &(from, to, offset)) in spans.iter().enumerate() {
+                let from = pending_start.take().unwrap_or(from);
+                if short(from, to) && i + 1 < spans.len() {
+                    pending_start = Some(from);
+                    continue;
+                }
+                match steps.last_mut() {
+                    Some(last) if short(from, to) || last.2 == offset => last.1 = to,
+                    _ => steps.push((from, to, offset)),
+                }
+            }
+            steps
+                .into_iter()
+                .map(|(from, to, offset)| build_note(bent(offset), held, from, to, tpb_ratio))
+                .collect()
+        }
+    }
+}
+
+// TPB 변환된 음을 박별 그리드(일반/셋잇단)에 스냅
+fn quantize_note(note: &mut Note, triplet_beats: &HashSet<u32>) {
+    let start_snapped = snap_to_grid(note.start, triplet_beats);
+    let end_snapped = snap_to_grid(note.end, triplet_beats);
+
+    // 최소 길이 보장 (시작 박의 그리드 한 칸)
+    let duration_snapped = end_snapped
+        .saturating_sub(start_snapped)
+        .max(grid_step(start_snapped, triplet_beats));
+
+    note.start = start_snapped;
+    note.end = start_snapped + duration_snapped;
+    note.duration = duration_snapped;
+}
+
+/// [`extract_midi_notes`] 결과
+#[derive(Debug, Clone)]
+pub struct ExtractedMidi {
+    pub notes: Vec<Note>,                // 시작 시각순 음들
+    pub bpm: u32,                        // 시작 BPM
+    pub tempo_changes: Vec<TempoChange>, // 템포 변경 목록
+    pub bar_map: BarMap,                 // 박자표로 만든 마디·박 지도
+    pub key: Key,                        // 조표(없으면 음 분포로 어림한) 조성
+}
+
+/// MIDI 파일 바이트에서 고른 트랙·채널([`ExtractOptions::source`])의 음(드럼 채널 제외)을 뽑아
+/// [`TPB`] 그리드로 양자화한다.
+/// 조성은 곡 첫 조표(FF 59), 조표가 없으면 뽑은 음의 분포로 어림한 조.
+/// 담을 음이 없으면 NoNotes / DrumOnly 오류.
+pub fn extract_midi_notes(
+    midi_data: &[u8],
+    options: &ExtractOptions,
+) -> Result<ExtractedMidi, ConversionError> {
+    let smf = midly::Smf::parse(midi_data).map_err(|e| ConversionError::InvalidMidi {
+        offset: corrupt_offset(midi_data).unwrap_or(0),
+        reason: e.to_string(),
+    })?;
+
+    // 원본 4분음표당 틱 수. SMPTE 는 초당 틱(fps × 서브프레임)을 가상 템포의 한 박 길이로 환산한다.
+    let (tpb, timecode) = match smf.header.timing {
+        midly::Timing::Metrical(t) => (t.as_int() as f64, false),
+        midly::Timing::Timecode(fps, subframes) => {
+            let ticks_per_second = fps.as_f32() as f64 * subframes as f64;
+            (ticks_per_second * 60.0 / SMPTE_DERIVED_BPM, true)
+        }
+    };
+    if tpb <= 0.0 {
+        return Err(ConversionError::UnsupportedTiming);
+    }
+
+    // 모든 템포 변경 이벤트 추출 (원본 BPM, 아직 클램프/폴딩 전)
+    // SMPTE 파일의 틱은 절대 시간이라 템포 메타가 있어도 재생 속도에 영향이 없다 → 가상 템포 하나.
+    let mut raw_tempos: Vec<(u32, f64)> = Vec::new();
+    if timecode {
+        raw_tempos.push((0, SMPTE_DERIVED_BPM));
+    }
+    for track in smf.tracks.iter().filter(|_| !timecode) {
+        let mut tick = 0u32;
+        for event in track {
+            tick += event.delta.as_int();
+            if let midly::TrackEventKind::Meta(midly::MetaMessage::Tempo(tempo)) = event.kind {
+                let us = tempo.as_int();
+                if us == 0 {
+                    continue; // 비정상 0값은 무시
+                }
+                raw_tempos.push((tick, 60_000_000.0 / us as f64));
+            }
+        }
+    }
+
+    // 박자표 변경 (원본 틱, 분자, 분모). 마디·박 위치를 알리는 데만 쓴다.
+    // 조표는 곡 전체의 임시표 표기에 쓰므로 가장 먼저 나오는 것 하나만 (원본 틱, 조)
+    let mut raw_signatures: Vec<(u32, u8, u8)> = Vec::new();
+    let mut key_signature: Option<(u32, Key)> = None;
+    for track in &smf.tracks {
+        let mut tick = 0u32;
+        for event in track {
+            tick += event.delta.as_int();
+            match event.kind {
+                midly::TrackEventKind::Meta(midly::MetaMessage::TimeSignature(num, pow, _, _))
+                    if num > 0 && pow <= 6 =>
+                {
+                    raw_signatures.push((tick, num, 1 << pow));
+                }
+                midly::TrackEventKind::Meta(midly::MetaMessage::KeySignature(fifths, minor))
+                    if key_signature.is_none_or(|(t, _)| tick < t) =>
+                {
+                    key_signature = Some((tick, Key::from_signature(fifths, minor)));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    // 템포 변경을 tick 순으로 정렬하고 중복 제거
+    raw_tempos.sort_by_key(|&(tick, _)| tick);
+    raw_tempos.dedup_by_key(|&mut (tick, _)| tick);
+
+    // 템포 폴딩 계수 d: MML T 상한(255)을 넘는 빠른 곡은 T를 정수배로 접고
+    // 음표 길이(틱)도 같은 비율로 줄여 실제 재생 시간을 보존한다.
+    // (예: 340 BPM → T170 + 모든 틱 ½배. 4분음표가 8분음표가 되어 재생 시간 동일)
+    // d 는 곡 전체 최고 템포 기준 하나로 정해 전 구간에 같은 타임스케일을 적용한다.
+    let max_bpm = raw_tempos.iter().map(|&(_, b)| b).fold(0.0_f64, f64::max);
+    let fold = if max_bpm > MML_TEMPO_MAX as f64 {
+        (max_bpm / MML_TEMPO_MAX as f64).ceil() as u32
+    } else {
+        1
+    }
+    .max(1);
+
+    // 폴딩된 원본 BPM을 MML T 범위(32~255)로 클램프
+    let fold_bpm = |raw: f64| ((raw / fold as f64).round() as u32).clamp(MML_TEMPO_MIN, MML_TEMPO_MAX);
+
+    // BPM - 첫 번째 템포 또는 기본값
+    let bpm = raw_tempos.first().map(|&(_, b)| fold_bpm(b)).unwrap_or(120);
+
+    // TPB 변환 비율에 폴딩 계수를 합쳐 노트·템포 틱에 일괄 적용
+    let tpb_ratio = TPB as f64 / tpb / fold as f64;
+
+    // 음표 추출
+    let bend_mode = options.pitch_bend;
+    let mut notes = Vec::new();
+    let mut drum_notes = 0; // 변환에서 빠지는 드럼 파트 음 (드럼만 있는 파일 안내용)
+    let drum_parts = DrumParts::from_tracks(&smf.tracks);
+    for (track_index, track) in smf.tracks.iter().enumerate() {
+        let track_name: Option<Arc<str>> = track.iter().find_map(|event| match event.kind {
+            midly::TrackEventKind::Meta(midly::MetaMessage::TrackName(name)) => {
+                let name = String::from_utf8_lossy(name).trim().to_string();
+                (!name.is_empty()).then(|| name.into())
+            }
+            _ => None,
+        });
+        let mut channel_programs: HashMap<u8, u8> = HashMap::new();
+        let mut active: HashMap<(u8, u8), HeldNote> = HashMap::new();
+        // 채널별 페달 상태. 건반을 떼도 페달이 밟혀 있으면 음은 페달을 뗄 때까지 울린다.
+        let mut sustain_on = [false; 16];
+        let mut sostenuto_on = [false; 16];
+        // 채널별 볼륨(CC7)·익스프레션(CC11). 음 시작 시점 값을 그 음의 음량으로 쓴다.
+        let mut volume = [127u8; 16];
+        let mut expression = [127u8; 16];
+        // 채널별 피치 벤드 (반음, 반올림 전)와 벤드 범위, 지금 고른 RPN
+        let mut bend = [0.0f64; 16];
+        let mut bend_range = [DEFAULT_BEND_RANGE; 16];
+        let mut rpn = [RPN_NULL; 16];
+        let mut tick = 0u32;
+
+        // 페달이 더는 붙잡지 않는, 건반을 뗀 음들을 tick 에서 끝낸다
+        let flush_released = |active: &mut HashMap<(u8, u8), HeldNote>,
+                              notes: &mut Vec<Note>,
+                              ch: u8,
+                              tick: u32,
+                              sustain: bool,
+                              sostenuto: bool| {
+            let ended: Vec<(u8, u8)> = active
+                .iter()
+                .filter(|&(&(c, _), h)| {
+                    c == ch && h.released && !sustain && !(h.sostenuto && sostenuto)
+                })
+                .map(|(&k, _)| k)
+                .collect();
+            for key in ended {
+                if let Some(h) = active.remove(&key) {
+                    notes.extend(finish_note(key.1, &h, tick, tpb_ratio, bend_mode));
+                }
+            }
+        };
+
+        for event in track {
+            tick += event.delta.as_int();
+
+            if let midly::TrackEventKind::Midi { channel, message } = event.kind {
+                let ch = channel.as_int();
+                let chi = ch as usize;
+
+                // velocity 0 NoteOn = NoteOff
+                let note_off = match message {
+                    midly::MidiMessage::NoteOn { key, vel } if vel.as_int() == 0 => {
+                        Some(key.as_int())
+                    }
+                    midly::MidiMessage::NoteOff { key, .. } => Some(key.as_int()),
+                    _ => None,
+                };
+                if let Some(note_num) = note_off {
+                    let held = sustain_on[chi]
+                        || (sostenuto_on[chi]
+                            && active.get(&(ch, note_num)).is_some_and(|h| h.sostenuto));
+                    if held {
+                        if let Some(h) = active.get_mut(&(ch, note_num)) {
+                            h.released = true;
+                        }
+                    } else if let Some(h) = active.remove(&(ch, note_num)) {
+                        notes.extend(finish_note(note_num, &h, tick, tpb_ratio, bend_mode));
+                    }
+                    continue;
+                }
+
+                match message {
+                    midly::MidiMessage::ProgramChange { program } => {
+                        channel_programs.insert(ch, program.as_int());
+                    }
+                    midly::MidiMessage::NoteOn { key, vel } => {
+                        let note_num = key.as_int();
+                        if !options.source.accepts(track_index, ch) {
+                            continue; // 고르지 않은 트랙·채널 (드럼 수에도 넣지 않음)
+                        }
+                        let percussion = drum_parts.is_drum(ch, tick);
+                        if !percussion || options.percussion {
+                            // 페달로 울리던 같은 음을 다시 치면 이전 음은 여기서 끝난다
+                            if let Some(h) = active.remove(&(ch, note_num))
+                                && h.released
+                            {
+                                notes.extend(finish_note(note_num, &h, tick, tpb_ratio, bend_mode));
+                            }
+                            let program = channel_programs.get(&ch).copied().unwrap_or(0);
+                            active.insert(
+                                (ch, note_num),
+                                HeldNote {
+                                    start: tick,
+                                    velocity: vel.as_int(),
+                                    volume: (volume[chi] as u32 * expression[chi] as u32 / 127)
+                                        as u8,
+                                    program,
+                                    track: track_index,
+                                    track_name: track_name.clone(),
+                                    channel: ch,
+                                    percussion,
+                                    released: false,
+                                    sostenuto: false,
+                                    bends: vec![(tick, bend[chi].round() as i8)],
+                                },
+                            );
+                        } else {
+                            drum_notes += 1;
+                        }
+                    }
+                    midly::MidiMessage::Controller { controller, value } => {
+                        let on = value.as_int() >= PEDAL_ON_THRESHOLD;
+                        match controller.as_int() {
+                            CC_VOLUME => volume[chi] = value.as_int(),
+                            CC_EXPRESSION => expression[chi] = value.as_int(),
+                            CC_RPN_MSB => rpn[chi].0 = value.as_int(),
+                            CC_RPN_LSB => rpn[chi].1 = value.as_int(),
+                            CC_DATA_ENTRY_MSB if rpn[chi] == (0, 0) => {
+                                bend_range[chi] = value.as_int() as f64 + bend_range[chi].fract();
+                            }
+                            CC_DATA_ENTRY_LSB if rpn[chi] == (0, 0) => {
+                                bend_range[chi] =
+                                    bend_range[chi].trunc() + value.as_int().min(99) as f64 / 100.0;
+                            }
+                            CC_SUSTAIN => {
+                                sustain_on[chi] = on;
+                                if !on {
+                                    flush_released(
+                                        &mut active, &mut notes, ch, tick, false, sostenuto_on[chi],
+                                    );
+                                }
+                            }
+                            CC_SOSTENUTO if options.sostenuto => {
+                                if on && !sostenuto_on[chi] {
+                                    // 밟는 순간 눌려 있는 음만 붙잡는다
+                                    for (&(c, _), h) in active.iter_mut() {
+                                        if c == ch && !h.released {
+                                            h.sostenuto = true;
+                                        }
+                                    }
+                                } else if !on {
+                                    flush_released(
+                                        &mut active, &mut notes, ch, tick, sustain_on[chi], false,
+                                    );
+                                    for (&(c, _), h) in active.iter_mut() {
+                                        if c == ch {
+                                            h.sostenuto = false;
+                                        }
+                                    }
+                                }
+                                sostenuto_on[chi] = on;
+                            }
+                            _ => {}
+                        }
+                    }
+                    midly::MidiMessage::PitchBend { bend: value }
+                        if bend_mode != PitchBendMode::Ignore =>
+                    {
+                        bend[chi] = value.as_f64() * bend_range[chi];
+                        let offset = bend[chi].round() as i8;
+                        for (&(c, _), h) in active.iter_mut() {
+                            if c == ch && h.bends.last().is_some_and(|&(_, o)| o != offset) {
+                                h.bends.push((tick, offset));
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        // 트랙이 끝날 때까지 페달에 붙잡혀 있던 음은 트랙 끝에서 끝낸다
+        for ((_, note_num), h) in active.into_iter().filter(|(_, h)| h.released) {
+            notes.extend(finish_note(note_num, &h, tick, tpb_ratio, bend_mode));
+        }
+    }
+
+    // 박별 그리드(일반/셋잇단)를 정하고 음·템포 변경을 스냅
+    let triplet_beats = detect_triplet_beats(notes.iter().map(|n| n.start));
+    for n in &mut notes {
+        quantize_note(n, &triplet_beats);
+    }
+    let tempo_changes_converted: Vec<TempoChange> = raw_tempos
+        .into_iter()
+        .map(|(tick, raw_bpm)| {
+            let tick_converted = (tick as f64 * tpb_ratio).round() as u32;
+            let tick_snapped = snap_to_grid(tick_converted, &triplet_beats);
+            TempoChange {
+                tick: tick_snapped,
+                bpm: fold_bpm(raw_bpm),
+            }
+        })
+        .collect();
+    let bar_map = BarMap::new(
+        raw_signatures
+            .into_iter()
+            .map(|(tick, numerator, denominator)| TimeSignature {
+                tick: snap_to_grid((tick as f64 * tpb_ratio).round() as u32, &triplet_beats),
+                numerator,
+                denominator,
+            })
+            .collect(),
+        fold,
+    );
+
+    // 정렬 및 중복 제거 (드럼 음과 다른 음은 음높이가 같아도 따로 둔다)
+    notes.sort_by(|a, b| {
+        a.start
+            .cmp(&b.start)
+            .then(b.note.cmp(&a.note))
+            .then(a.percussion.cmp(&b.percussion))
+    });
+
+    let mut deduplicated = Vec::new();
+    let mut i = 0;
+    while i < notes.len() {
+        let current = &notes[i];
+        let mut duplicates = vec![current.clone()];
+        let mut j = i + 1;
+
+        while j < notes.len()
+            && notes[j].start == current.start
+            && notes[j].note == current.note
+            && notes[j].percussion == current.percussion
+        {
+            duplicates.push(notes[j].clone());
+            j += 1;
+        }
+
+        let best = duplicates
+            .into_iter()
+            .max_by_key(|n| n.velocity)
+            .unwrap();
+        deduplicated.push(best);
+        i = j;
+    }
+
+    if deduplicated.is_empty() {
+        return Err(match drum_notes {
+            0 => ConversionError::NoNotes,
+            drum_notes => ConversionError::DrumOnly { drum_notes },
+        });
+    }
+    let key = key_signature.map_or_else(|| Key::estimate(&deduplicated), |(_, key)| key);
+    Ok(ExtractedMidi {
+        notes: deduplicated,
+        bpm,
+        tempo_changes: tempo_changes_converted,
+        bar_map,
+        key,
+    })
+}
+
+// midly 가 읽다 실패한 바이트 위치. 헤더 → 트랙 청크 → 이벤트 순으로 다시 읽어 처음 실패한 곳을 찾는다.
+fn corrupt_offset(midi_data: &[u8]) -> Option<usize> {
+    let offset = |unread: &[u8]| midi_data.len() - unread.len();
+    let Ok((_, mut tracks)) = midly::parse(midi_data) else {
+        return Some(0);
+    };
+    loop {
+        let track_start = offset(tracks.unread());
+        let mut events = match tracks.next()? {
+            Err(_) => return Some(track_start),
+            Ok(events) => events,
+        };
+        loop {
+            let event_start = offset(events.unread());
+            match events.next() {
+                None => break,
+                Some(Err(_)) => return Some(event_start),
+                Some(Ok(_)) => {}
+            }
+        }
+    }
+}
+
+/// MIDI 음의 MML 옥타브. 유효 범위(O1~O8) 밖이면 범위 끝으로 클램프하고 false
+/// (클램프된 음은 옥타브가 틀린 음으로 연주된다).
+pub fn mml_octave(note: u8) -> (i32, bool) {
+    let raw = note as i32 / 12 - 1;
+    let octave = raw.clamp(MML_OCTAVE_MIN, MML_OCTAVE_MAX);
+    (octave, octave == raw)
+}
+
+/// 보이스가 연주할 MML 옥타브 범위 (양 끝 포함). 기본은 MML 유효 범위 O1~O8.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OctaveRange {
+    pub min: i32,
+    pub max: i32,
+}
+
+impl Default for OctaveRange {
+    fn default() -> Self {
+        Self {
+            min: MML_OCTAVE_MIN,
+            max: MML_OCTAVE_MAX,
+        }
+    }
+}
+
+/// 범위 밖 음을 옥타브 단위로 범위 안쪽(보이스 가운데 쪽)으로 접는다. 음이름은 그대로.
+/// 범위는 MML 유효 범위(O1~O8) 안으로 줄여 쓴다.
+pub fn fold_octaves(notes: Vec<Note>, range: OctaveRange) -> Vec<Note> {
+    let min = range.min.clamp(MML_OCTAVE_MIN, MML_OCTAVE_MAX);
+    let max = range.max.clamp(min, MML_OCTAVE_MAX);
+    let lowest = (min + 1) * 12; // O{min} 의 C
+    let highest = ((max + 2) * 12 - 1).min(127); // O{max} 의 B
+    notes
+        .into_iter()
+        .map(|n| {
+            let mut pitch = n.note as i32;
+            while pitch < lowest {
+                pitch += 12;
+            }
+            while pitch > highest {
+                pitch -= 12;
+            }
+            Note {
+                note: pitch as u8,
+                ..n
+            }
+        })
+        .collect()
+}
+
+/// 모든 음을 semitones 반음만큼 옮긴다 (MIDI 범위 0~127 안으로).
+pub fn transpose_notes(notes: Vec<Note>, semitones: i32) -> Vec<Note> {
+    if semitones == 0 {
+        return notes;
+    }
+    notes
+        .into_iter()
+        .map(|n| Note {
+            note: (n.note as i32 + semitones).clamp(0, 127) as u8,
+            ..n
+        })
+        .collect()
+}
+
+/// 폴리포니 노트를 최대 max_voices 개의 단음 보이스로 분배.
+/// 멜로디(최고음)와 베이스(최저음)는 항상 보호하고, 모든 레인이 울리는 중이면
+/// 가운데 내성부 음의 꼬리를 잘라(steal) 새 음에게 자리를 내준다.
+/// 반환 보이스는 평균 음높이 내림차순(멜로디 → 베이스)으로 정렬된다.
+pub fn allocate_voices_capped(mut notes: Vec<Note>, max_voices: usize) -> Vec<Vec<Note>> {
+    if max_voices == 0 || notes.is_empty() {
+        return Vec::new();
+    }
+
+    // 시작 tick 오름차순, 동시 시작이면 높은 음 우선
+    notes.sort_by(|a, b| a.start.cmp(&b.start).then(b.note.cmp(&a.note)));
+
+    let mut lanes: Vec<Vec<Note>> = vec![Vec::new(); max_voices];
+
+    for n in notes {
+        // 1) 비어있는(직전 음이 끝난) 레인 중 음높이가 가장 가까운 곳에 배치 → 선율 연속성
+        let mut target: Option<usize> = None;
+        let mut best_gap = i32::MAX;
+        for (i, lane) in lanes.iter().enumerate() {
+            let free = lane.last().is_none_or(|l| l.end <= n.start);
+            if free {
+                let gap = lane
+                    .last()
+                    .map_or(0, |l| (l.note as i32 - n.note as i32).abs());
+                if gap < best_gap {
+                    best_gap = gap;
+                    target = Some(i);
+                }
+            }
+        }
+        if let Some(i) = target {
+            lanes[i].push(n);
+            continue;
+        }
+
+        // 2) 모든 레인이 울리는 중 → 멜로디/베이스는 보호, 가운데 내성부의 꼬리를 잘라 자리 확보.
+        //    (동시에 시작한 음은 자르면 길이가 0이 되므로 victim 후보에서 제외)
+        let hi = lanes
+            .iter()
+            .filter_map(|l| l.last().map(|x| x.note))
+            .max()
+            .unwrap_or(0);
+        let lo = lanes
+            .iter()
+            .filter_map(|l| l.last().map(|x| x.note))
+            .min()
+            .unwrap_or(0);
+
+        // 내성부 victim 후보: 꼬리를 자를 수 있는 것(시간차) vs 교체만 가능한 동시발음
+        let mut trunc_victim: Option<usize> = None; // 직전 음을 잘라 둘 다 보존
+        let mut trunc_gap = i32::MAX;
+        let mut repl_victim: Option<usize> = None; // 동시발음이라 교체(직전 음 버림)만 가능
+        let mut repl_gap = i32::MAX;
+        for (i, lane) in lanes.iter().enumerate() {
+            if let Some(last) = lane.last() {
+                if last.note == hi || last.note == lo {
+                    continue; // 멜로디/베이스 보호
+                }
+                let gap = (last.note as i32 - n.note as i32).abs();
+                if last.start < n.start {
+                    if gap < trunc_gap {
+                        trunc_gap = gap;
+                        trunc_victim = Some(i);
+                    }
+                } else if gap < repl_gap {
+                    repl_gap = gap;
+                    repl_victim = Some(i);
+                }
+            }
+        }
+
+        if let Some(i) = trunc_victim {
+            // 내성부 직전 음의 꼬리를 잘라 자리 확보 → 직전 음과 새 음 모두 보존
+            if let Some(last) = lanes[i].last_mut() {
+                last.end = n.start;
+                last.duration = n.start.saturating_sub(last.start);
+            }
+            lanes[i].push(n);
+        } else if n.note > hi || n.note < lo {
+            // 자를 게 없지만 새 음이 새 멜로디/베이스면 동시발음 내성부 하나를 교체
+            if let Some(i) = repl_victim {
+                lanes[i].pop();
+                lanes[i].push(n);
+            }
+        }
+        // 그 외(보호 대상만 울리거나 새 음이 내성부)면 새 음은 버린다.
+    }
+
+    // 빈 레인 제거 후 평균 음높이 내림차순 정렬(멜로디가 앞으로)
+    let mut voices: Vec<Vec<Note>> = lanes.into_iter().filter(|l| !l.is_empty()).collect();
+    voices.sort_by_key(|b| std::cmp::Reverse(avg_pitch(b)));
+    voices
+}
+
+// 보이스 평균 음높이
+fn avg_pitch(voice: &[Note]) -> u32 {
+    if voice.is_empty() {
+        return 0;
+    }
+    voice.iter().map(|n| n.note as u32).sum::<u32>() / voice.len() as u32
+}
+
+/// 텍스처의 바닥음(베이스)을 멜로디·화음에서 분리한다 (2인 모드용).
+/// 어떤 음이 울리는 동안 그보다 높은 음은 있고 더 낮은 음은 없으면 = 그 화음의 바닥 = 베이스.
+/// (위/아래 아무것도 없는 단선율 멜로디 음은 베이스가 아니라 멜로디 쪽으로 간다.)
+/// 반환: (베이스 음들, 나머지 음들)
+pub fn split_bass_line(notes: Vec<Note>) -> (Vec<Note>, Vec<Note>) {
+    let mut bass = Vec::new();
+    let mut rest = Vec::new();
+    for n in &notes {
+        let sounding = |cmp: &dyn Fn(u8) -> bool| {
+            notes
+                .iter()
+                .any(|m| m.start <= n.start && m.end > n.start && cmp(m.note))
+        };
+        let has_higher = sounding(&|p| p > n.note);
+        let has_lower = sounding(&|p| p < n.note);
+        if has_higher && !has_lower {
+            bass.push(n.clone());
+        } else {
+            rest.push(n.clone());
+        }
+    }
+    (bass, rest)
+}
+
+/// 멜로디로 고정할 음(is_melody)을 단음 한 줄로 떼어 낸다 (트랙·채널로 멜로디를 지정할 때).
+/// 고정 트랙 안에서 겹치는 음은 뒤 음이 시작할 때 앞 음 꼬리를 자르고,
+/// 같은 시각에 시작한 음은 가장 높은 음만 멜로디에 남긴다. 남은 음은 나머지로 돌려보낸다.
+/// 반환: (멜로디 음들, 나머지 음들)
+pub fn split_melody_line(
+    notes: Vec<Note>,
+    is_melody: impl Fn(&Note) -> bool,
+) -> (Vec<Note>, Vec<Note>) {
+    let (mut pinned, mut rest): (Vec<Note>, Vec<Note>) = notes.into_iter().partition(is_melody);
+    pinned.sort_by(|a, b| a.start.cmp(&b.start).then(b.note.cmp(&a.note)));
+
+    let mut melody: Vec<Note> = Vec::with_capacity(pinned.len());
+    for n in pinned {
+        match melody.last_mut() {
+            Some(last) if last.start == n.start => rest.push(n),
+            Some(last) if last.end > n.start => {
+                last.end = n.start;
+                last.duration = n.start - last.start;
+                melody.push(n);
+            }
+            _ => melody.push(n),
+        }
+    }
+    (melody, rest)
+}
+
+/// 구간 겹침 기준 최대 동시발음 수 (이 악기가 실제로 필요로 하는 보이스 수)
+pub fn max_polyphony(notes: &[Note]) -> usize {
+    let mut events: Vec<(u32, i32)> = Vec::with_capacity(notes.len() * 2);
+    for n in notes {
+        events.push((n.start, 1));
+        events.push((n.end, -1));
+    }
+    events.sort_by(|a, b| a.0.cmp(&b.0).then(a.1.cmp(&b.1)));
+    let mut cur = 0i32;
+    let mut max = 0i32;
+    for (_, d) in events {
+        cur += d;
+        max = max.max(cur);
+    }
+    max as usize
+}
+
+// 악기군(family = program/8, 현악·피아노·금관…)별로 그룹화하고 노트 수(중요도) 내림차순 정렬.
+// 같은 음색(예: 바이올린·비올라·첼로 = 모두 현악)을 한 덩어리로 묶어 그룹 수를 줄인다.
+// 악기(program) 단위로 잘게 쪼개 상위 N개만 남기던 방식보다 그룹이 적어지므로,
+// 노트 수가 적어 단독이면 잘려나갈 보조 악기(인트로 패드 등)도 살아남을 여지가 생긴다
+// → 곡 앞부분이 통째로 묵음 되던 문제를 줄인다.
+fn group_by_family(notes: Vec<Note>) -> Vec<Vec<Note>> {
+    let mut groups: HashMap<u8, Vec<Note>> = HashMap::new();
+    for n in notes {
+        groups.entry(n.program / 8).or_default().push(n);
+    }
+    let mut groups: Vec<Vec<Note>> = groups.into_values().collect();
+    // 노트 수 많은 음색군 = 중요도 높음
+    groups.sort_by_key(|b| std::cmp::Reverse(b.len()));
+    groups
+}
+
+/// 악기군 인지 보이스 분배 (총 max_voices 예산).
+/// - 음색군이 하나면 전 예산을 그 군에 (단음이면 1보이스만 나옴).
+/// - 여러 군이면 군당 최대 PER_FAMILY_CAP(3)까지, 중요도(노트 수) 순으로
+///   레벨별로 한 보이스씩 돌아가며 채운다 → 중요한 음색이 더 받되 최대한 많은 음색을 대표.
+/// - 끝으로, 악기가 적은 구간(인트로 등)에 놀고 있는 보이스의 빈틈에 아직 안 담긴 음을
+///   흡수시킨다(gap-fill) → 보이스가 놀아서 곡 앞부분이 짤리던 문제 해결, 음색 구분은 유지.
+pub fn allocate_voices_by_instrument(notes: Vec<Note>, max_voices: usize) -> Vec<Vec<Note>> {
+    if max_voices == 0 || notes.is_empty() {
+        return Vec::new();
+    }
+
+    let all = notes.clone();
+    let groups = group_by_family(notes);
+
+    // 음색군 1개 → 전 예산을 그 군에 (전 보이스가 한 음색이라 노는 보이스 없음 → gap-fill 불필요)
+    if groups.len() <= 1 {
+        let only = groups.into_iter().next().unwrap_or_default();
+        return allocate_voices_capped(only, max_voices);
+    }
+
+    // 여러 음색군: 군당 min(실제 동시발음, 3)까지, 중요도순 레벨별 분배
+    const PER_FAMILY_CAP: usize = 3;
+    let caps: Vec<usize> = groups
+        .iter()
+        .map(|g| max_polyphony(g).min(PER_FAMILY_CAP))
+        .collect();
+    let mut alloc = vec![0usize; groups.len()];
+    let mut remaining = max_voices;
+
+    'fill: loop {
+        let mut progressed = false;
+        for (i, a) in alloc.iter_mut().enumerate() {
+            if remaining == 0 {
+                break 'fill;
+            }
+            if *a < caps[i] {
+                *a += 1;
+                remaining -= 1;
+                progressed = true;
+            }
+        }
+        if !progressed {
+            break; // 모든 악기가 상한에 도달
+        }
+    }
+
+    // 악기별로 배정된 보이스 수만큼 capped 분배
+    let mut voices = Vec::new();
+    for (group, a) in groups.into_iter().zip(alloc) {
+        if a > 0 {
+            voices.extend(allocate_voices_capped(group, a));
+        }
+    }
+
+    // 아직 어느 보이스에도 안 담긴 음(드롭/씹힌 음)을, 그 시각에 비어있는 보이스 빈틈에 흡수.
+    // capped 가 음을 자를 때 start/note/program 은 그대로라 이 키로 "담긴 음"을 식별한다.
+    let placed: std::collections::HashSet<(u32, u8, u8)> = voices
+        .iter()
+        .flatten()
+        .map(|n| (n.start, n.note, n.program))
+        .collect();
+    let leftover: Vec<Note> = all
+        .into_iter()
+        .filter(|n| !placed.contains(&(n.start, n.note, n.program)))
+        .collect();
+    fill_idle_gaps(&mut voices, leftover);
+
+    voices
+}
+
+// 아직 안 담긴 음들을, 그 음의 (start,end) 구간이 통째로 비어있는 보이스에 끼워넣는다.
+// 같은 음색 보이스를 우선하고, 다른 음색은 그 보이스의 정체성(다수결 음색)을 깨지 않는 선에서만
+// 받는다 → 악기 적은 구간(인트로 등)에 놀던 보이스를 활용하되, 6개 음색 구분은 유지.
+// 보이스 수는 늘리지 않는다(빈틈만 채움).
+fn fill_idle_gaps(voices: &mut [Vec<Note>], leftover: Vec<Note>) {
+    if voices.is_empty() || leftover.is_empty() {
+        return;
+    }
+
+    // 1차 배분 직후 각 보이스는 단일 음색 → 대표 음색과 그 크기를 기록.
+    // 다른 음색 음은 이 크기를 넘지 않는 선까지만 받아 대표 음색이 항상 다수가 되게 한다.
+    let voice_fam: Vec<u8> = voices
+        .iter()
+        .map(|v| v.first().map(|n| n.program / 8).unwrap_or(0))
+        .collect();
+    let primary_count: Vec<usize> = voices.iter().map(|v| v.len()).collect();
+    let mut cross_added = vec![0usize; voices.len()];
+
+    let mut left = leftover;
+    // 시작 빠른 순, 동시면 높은 음 우선
+    left.sort_by(|a, b| a.start.cmp(&b.start).then(b.note.cmp(&a.note)));
+
+    for n in left {
+        let nf = n.program / 8;
+        let mut best: Option<usize> = None;
+        let mut best_key = (u8::MAX, i32::MAX);
+        for (i, v) in voices.iter().enumerate() {
+            // n 의 구간이 이 보이스의 빈틈에 통째로 들어가는가(겹침 없음)
+            let free = v.iter().all(|m| m.end <= n.start || m.start >= n.end);
+            if !free {
+                continue;
+            }
+            let cross = nf != voice_fam[i];
+            // 다른 음색은 대표 음색이 다수로 남는 선까지만 (정체성 보호)
+            if cross && cross_added[i] + 1 >= primary_count[i] {
+                continue;
+            }
+            // 이웃 음과의 음높이 차(빈 보이스면 0)
+            let pitch_gap = v
+                .iter()
+                .map(|m| (m.note as i32 - n.note as i32).abs())
+                .min()
+                .unwrap_or(0);
+            let key = (if cross { 1u8 } else { 0 }, pitch_gap);
+            if key < best_key {
+                best_key = key;
+                best = Some(i);
+            }
+        }
+        if let Some(i) = best {
+            if nf != voice_fam[i] {
+                cross_added[i] += 1;
+            }
+            let pos = voices[i].partition_point(|m| m.start <= n.start);
+            voices[i].insert(pos, n);
+        }
+    }
+}
+
+// 길이(틱)를 쉼표 토큰들로 출력하고 실제 출력된 틱 합을 반환
+fn push_rest(
+    mml: &mut Vec<String>,
+    ticks: u32,
+    default_length: &str,
+    exact_lengths: &HashMap<u32, &str>,
+) -> u32 {
+    let mut emitted = 0;
+    for (len_str, len_ticks) in find_best_length(ticks, 4, exact_lengths) {
+        if len_str == default_length {
+            mml.push("R".to_string());
+        } else {
+            mml.push(format!("R{}", len_str));
+        }
+        emitted += len_ticks;
+    }
+    emitted
+}
+
+// 한 음을 길이(틱)만큼 출력. lead_tie=true 면 맨 앞에 타이(&)로 직전 음과 연결.
+// 실제 출력된 틱 합을 반환.
+fn push_note(
+    mml: &mut Vec<String>,
+    note_name: &str,
+    ticks: u32,
+    octave: i32,
+    lead_tie: bool,
+    default_length: &str,
+    exact_lengths: &HashMap<u32, &str>,
+) -> u32 {
+    let mut emitted = 0;
+    for (i, (len_str, len_ticks)) in find_best_length(ticks, octave, exact_lengths)
+        .into_iter()
+        .enumerate()
+    {
+        if lead_tie || i > 0 {
+            mml.push("&".to_string());
+        }
+        if len_str == default_length {
+            mml.push(note_name.to_string());
+        } else {
+            mml.push(format!("{}{}", note_name, len_str));
+        }
+        emitted += len_ticks;
+    }
+    emitted
+}
+
+// 음의 셈여림(벨로시티 × 채널 음량)을 MML 음량 V1~V15 로. 소리 나는 음은 V0(무음)이 되지 않게 한다.
+fn mml_volume(note: &Note) -> u32 {
+    let loudness = note.velocity as u32 * note.volume as u32; // 0 ~ 127²
+    ((loudness * MML_VOLUME_MAX + 127 * 127 / 2) / (127 * 127)).max(1)
+}
+
+/// 단음 보이스 하나를 마비노기 MML 한 줄로 만든다.
+/// 헤더(T/V/O/L) 뒤에 템포·음량·옥타브 변경과 음·쉼표를 적는다. 음이 없으면 빈 문자열.
+/// 기본 길이 L 은 구간마다 이득이 될 때 바꿔 적는다 (optimize_mml).
+pub fn generate_mml_final(
+    voice_notes: &[Note],
+    bpm: u32,
+    start_octave: i32,
+    tempo_changes: &[TempoChange],
+    options: &MmlOptions,
+) -> String {
+    generate_mml_with_offsets(voice_notes, bpm, start_octave, tempo_changes, options).0
+}
+
+/// generate_mml_final 과 같은 MML 과 함께, 음마다 그 음까지 적는 데 든 누적 글자 수를 돌려준다.
+/// offsets[i] 는 헤더부터 i번째 음(타이로 이은 조각까지)이 끝나는 위치라, MML 을 다시 만들지 않고도
+/// 앞에서 몇 음까지 글자수 한도 안에 드는지 바로 알 수 있다. 음을 적으면서 함께 기록한다.
+pub fn generate_mml_with_offsets(
+    voice_notes: &[Note],
+    bpm: u32,
+    start_octave: i32,
+    tempo_changes: &[TempoChange],
+    options: &MmlOptions,
+) -> (String, Vec<usize>) {
+    let (mml, offsets) =
+        generate_mml_single_length(voice_notes, bpm, start_octave, tempo_changes, options);
+    optimize_mml_with_offsets(&mml, &offsets)
+}
+
+// 보이스 전체에 가장 많이 쓰인 길이 하나를 L 로 두고 그대로 적은 MML (최적화 전)과 음별 누적 글자 수
+fn generate_mml_single_length(
+    voice_notes: &[Note],
+    bpm: u32,
+    start_octave: i32,
+    tempo_changes: &[TempoChange],
+    options: &MmlOptions,
+) -> (String, Vec<usize>) {
+    if voice_notes.is_empty() {
+        return (String::new(), Vec::new());
+    }
+
+    let exact_lengths = get_exact_lengths();
+    let mut mml = Vec::new();
+
+    // 헤더 (셈여림 반영 시 첫 음의 음량으로 시작)
+    let mut current_volume = match options.dynamics {
+        Some(_) => mml_volume(&voice_notes[0]),
+        None => MML_VOLUME_MAX,
+    };
+    mml.push(format!("T{}", bpm));
+    mml.push(format!("V{}", current_volume));
+    mml.push(format!("O{}", start_octave));
+
+    let mut current_octave = start_octave;
+    let mut tempo_change_index = 1; // 0은 시작 템포이므로 1부터 시작
+
+    // 기본 길이 계산
+    let mut length_counts: HashMap<String, usize> = HashMap::new();
+    for note in voice_notes {
+        let octave = (note.note as i32 / 12) - 1;
+        let lengths = find_best_length(note.duration, octave, &exact_lengths);
+        let first_length = lengths[0].0.trim_end_matches('.').to_string();
+        *length_counts.entry(first_length).or_insert(0) += 1;
+    }
+
+    let mut default_length = "8".to_string();
+    for preferred in &["8", "16", "4"] {
+        if length_counts.contains_key(*preferred) {
+            default_length = preferred.to_string();
+            break;
+        }
+    }
+    if default_length == "8" && !length_counts.contains_key("8") {
+        // 가장 흔한 길이. 동점이면 길이 문자열 기준으로 정해서 결정적으로 만든다.
+        if let Some((max_key, _)) = length_counts
+            .iter()
+            .max_by(|a, b| a.1.cmp(b.1).then_with(|| a.0.cmp(b.0)))
+        {
+            default_length = max_key.clone();
+        }
+    }
+
+    mml.push(format!("L{}", default_length));
+
+    let mut current_tick = 0u32;
+    let mut note_end_tokens = Vec::with_capacity(voice_notes.len()); // 음마다 끝난 뒤의 토큰 수
+
+    for note in voice_notes {
+        let note_end = note.start + note.duration;
+
+        // 1) note.start 이전(갭/쉼표 구간)에 위치한 템포 변경 삽입
+        while tempo_change_index < tempo_changes.len()
+            && tempo_changes[tempo_change_index].tick <= note.start
+        {
+            let t = &tempo_changes[tempo_change_index];
+            let gap = t.tick.saturating_sub(current_tick);
+            if gap > 0 {
+                current_tick += push_rest(&mut mml, gap, &default_length, &exact_lengths);
+            }
+            mml.push(format!("T{}", t.bpm));
+            tempo_change_index += 1;
+        }
+
+        // 2) note.start 까지 남은 갭 쉼표
+        let gap = note.start.saturating_sub(current_tick);
+        if gap > 0 {
+            current_tick += push_rest(&mut mml, gap, &default_length, &exact_lengths);
+        }
+
+        // 3) 음량 명령: 직전 V 와 히스테리시스 이상 차이 날 때만 새로 적는다
+        if let Some(hysteresis) = options.dynamics {
+            let volume = mml_volume(note);
+            if volume.abs_diff(current_volume) >= (hysteresis as u32).max(1) {
+                mml.push(format!("V{}", volume));
+                current_volume = volume;
+            }
+        }
+
+        // 4) 옥타브 명령 (MML 유효 범위로 클램프 — O0/음수/O9 같은 잘못된 토큰 방지)
+        let (note_name, _) = midi_to_note_name(note.note, options.flats);
+        let (octave, _) = mml_octave(note.note);
+        // 옥타브 변경: ±1은 상대 명령(>,<)으로 1자 절약, 그 외엔 절대값 O{n}
+        if octave != current_octave {
+            match octave - current_octave {
+                1 => mml.push(">".to_string()),
+                -1 => mml.push("<".to_string()),
+                _ => mml.push(format!("O{}", octave)),
+            }
+            current_octave = octave;
+        }
+
+        // 5) 음표 출력 - 음 길이 도중에 걸리는 템포 변경은 타이(&)로 분할하고 사이에 T 삽입
+        let mut seg_start = note.start;
+        let mut lead_tie = false; // 첫 세그먼트는 타이 없이 시작
+        loop {
+            // 이번 세그먼트 (seg_start, note_end) 안쪽에 걸리는 다음 템포 변경
+            let split_tick = if tempo_change_index < tempo_changes.len()
+                && tempo_changes[tempo_change_index].tick > seg_start
+                && tempo_changes[tempo_change_index].tick < note_end
+            {
+                Some(tempo_changes[tempo_change_index].tick)
+            } else {
+                None
+            };
+
+            let seg_end = split_tick.unwrap_or(note_end);
+            let seg_ticks = seg_end.saturating_sub(seg_start);
+            if seg_ticks > 0 {
+                current_tick += push_note(
+                    &mut mml,
+                    &note_name,
+                    seg_ticks,
+                    octave,
+                    lead_tie,
+                    &default_length,
+                    &exact_lengths,
+                );
+                lead_tie = true; // 다음 세그먼트는 타이로 연결
+            }
+
+            match split_tick {
+                Some(_) => {
+                    let t = &tempo_changes[tempo_change_index];
+                    mml.push(format!("T{}", t.bpm));
+                    tempo_change_index += 1;
+                    seg_start = seg_end;
+                }
+                None => break,
+            }
+        }
+        note_end_tokens.push(mml.len());
+    }
+
+    let mut offsets = Vec::with_capacity(note_end_tokens.len());
+    let mut written = 0;
+    let mut counted = 0;
+    for end in note_end_tokens {
+        written += mml[counted..end].iter().map(String::len).sum::<usize>();
+        counted = end;
+        offsets.push(written);
+    }
+    (mml.join(""), offsets)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mml_parser::parse_mml;
+
+    fn note(num: u8, start: u32, duration: u32) -> Note {
+        Note {
+            note: num,
+            start,
+            end: start + duration,
+            duration,
+            velocity: 100,
+            volume: 127,
+            program: 0,
+            track: 0,
+            track_name: None,
+            channel: 0,
+            percussion: false,
+        }
+    }
+
+    fn note_prog(num: u8, start: u32, duration: u32, program: u8) -> Note {
+        Note {
+            program,
+            ..note(num, start, duration)
+        }
+    }
+
+    // 생성 단계: ±1 옥타브 이동은 >/< 로, 잠깐 갔다 오면 B+ 로 줄어야 한다.
+    #[test]
+    fn relative_octave_in_generation() {
+        let notes = vec![note(60, 0, 384), note(72, 384, 384), note(60, 768, 384)]; // C4→C5→C4
+        let tempos = vec![TempoChange { tick: 0, bpm: 120 }];
+        let mml = generate_mml_final(&notes, 120, 4, &tempos, &MmlOptions::default());
+        assert!(mml.contains("B+"), "위 바운스가 B+로 안 줄음: {mml}");
+        assert!(!mml.contains("O5"), "절대 옥타브 O5가 남음(상대화 실패): {mml}");
+    }
+
+    // 플랫 조에선 검은 건반을 플랫(-)으로 적고, 최적화를 거쳐도 플랫이 유지된다.
+    #[test]
+    fn flat_keys_spell_accidentals_with_flats() {
+        let notes = vec![note(70, 0, 384), note(63, 384, 384), note(72, 768, 384)]; // B♭4 E♭4 C5
+        let tempos = vec![TempoChange { tick: 0, bpm: 120 }];
+        let flats = MmlOptions {
+            flats: true,
+            ..Default::default()
+        };
+        let mml = generate_mml_final(&notes, 120, 4, &tempos, &flats);
+        assert!(mml.contains("B-") && mml.contains("E-"), "플랫으로 안 적음: {mml}");
+        assert!(!mml.contains('+') || mml.contains("B+"), "샵이 남음: {mml}");
+        let sharps = generate_mml_final(&notes, 120, 4, &tempos, &MmlOptions::default());
+        assert!(sharps.contains("A+") && sharps.contains("D+"), "{sharps}");
+        assert_eq!(mml.len(), sharps.len(), "표기만 다르고 글자 수는 같아야 함");
+    }
+
+    // 느린 도입부 + 빠른 후렴: 후렴 앞에서 L 을 바꿔 한 가지 L 로 적을 때보다 짧아진다.
+    #[test]
+    fn default_length_changes_between_sections() {
+        let tempos = [TempoChange { tick: 0, bpm: 120 }];
+        let intro = (0..16u32).map(|i| note(60 + (i % 5) as u8, i * 768, 768));
+        let chorus = (0..64u32).map(|i| note(64 + (i % 3) as u8, 16 * 768 + i * 96, 96));
+        let notes: Vec<Note> = intro.chain(chorus).collect();
+        let options = MmlOptions::default();
+
+        let (single, _) = generate_mml_single_length(&notes, 120, 4, &tempos, &options);
+        let mml = generate_mml_final(&notes, 120, 4, &tempos, &options);
+        assert!(mml.len() < single.len(), "{mml} / {single}");
+        assert_eq!(mml.matches('L').count(), 2, "도입부와 후렴에 L 하나씩: {mml}");
+    }
+
+    // 구간별 L 을 적은 결과는 어떤 보이스든 한 가지 L 로 적은 것보다 길어지지 않는다.
+    #[test]
+    fn dynamic_default_length_never_grows() {
+        const LENGTHS: [u32; 8] = [96, 192, 288, 384, 576, 768, 128, 1536];
+        let tempos = [TempoChange { tick: 0, bpm: 120 }];
+        let options = MmlOptions::default();
+        let mut seed = 12345u32;
+        let mut next = |n: u32| {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            (seed >> 16) % n
+        };
+        for _ in 0..30 {
+            let mut notes = Vec::new();
+            let mut tick = 0;
+            for _ in 0..(20 + next(60)) {
+                tick += [0, 0, 0, 96, 384, 1536][next(6) as usize];
+                let duration = LENGTHS[next(LENGTHS.len() as u32) as usize];
+                notes.push(note(36 + next(60) as u8, tick, duration));
+                tick += duration;
+            }
+            let (single, _) = generate_mml_single_length(&notes, 120, 4, &tempos, &options);
+            let mml = generate_mml_final(&notes, 120, 4, &tempos, &options);
+            assert!(mml.len() <= single.len(), "{mml} / {single}");
+        }
+    }
+
+    // 노트 길이 한가운데에 걸리는 템포 변경이 누락되지 않고, 타이로 분할되어 삽입되어야 한다.
+    #[test]
+    fn tempo_change_inside_a_note_is_emitted_with_tie_split() {
+        let notes = vec![note(60, 384, 768)]; // C4, tick 384~1152 (틱 768을 통과)
+        let tempos = vec![
+            TempoChange { tick: 0, bpm: 120 },
+            TempoChange { tick: 768, bpm: 90 },
+        ];
+
+        let mml = generate_mml_final(&notes, 120, 4, &tempos, &MmlOptions::default());
+
+        assert!(mml.contains("T90"), "노트 중간 템포가 누락됨: {mml}");
+        assert!(mml.contains("T90&"), "템포 경계에서 타이 분할이 안 됨: {mml}");
+    }
+
+    // 음별 누적 글자 수는 음마다 하나씩, 타이로 이은 마지막 조각 끝에 찍힌다.
+    #[test]
+    fn offsets_mark_where_each_note_ends() {
+        let notes = vec![note(60, 0, 480), note(62, 576, 384), note(62, 960, 768), note(72, 1728, 96)];
+        let tempos = vec![
+            TempoChange { tick: 0, bpm: 120 },
+            TempoChange { tick: 1152, bpm: 90 },
+        ];
+        let options = MmlOptions::default();
+        let (mml, offsets) = generate_mml_with_offsets(&notes, 120, 4, &tempos, &options);
+        assert_eq!(mml, generate_mml_final(&notes, 120, 4, &tempos, &options));
+        assert_eq!(offsets.len(), notes.len());
+        assert_eq!(offsets.last(), Some(&mml.len()));
+        for (k, &end) in offsets.iter().enumerate() {
+            let prefix = parse_mml(&mml[..end]).unwrap();
+            assert_eq!(prefix.notes.len(), k + 1, "{}", &mml[..end]);
+            assert_eq!(prefix.notes[k].end, notes[k].end, "{}", &mml[..end]);
+        }
+    }
+
+    // 타이로 이어 적는 긴 음 바로 뒤에 같은 높이 음이 붙고, 템포 변경에 걸려 타이로 나뉜 음이 있어도
+    // 누적 글자 수는 원래 음 하나에 하나씩, 그 음의 마지막 조각 끝에 찍힌다.
+    #[test]
+    fn offsets_stay_aligned_with_tied_same_pitch_notes() {
+        let notes = vec![
+            note(60, 0, 1920),    // C1&C4
+            note(60, 1920, 192),  // 바로 붙은 같은 음
+            note(60, 2112, 384),  // 템포 변경에 걸려 C&T90C
+            note(60, 2496, 96),
+            note(62, 2592, 1920),
+        ];
+        let tempos = vec![
+            TempoChange { tick: 0, bpm: 120 },
+            TempoChange { tick: 2304, bpm: 90 },
+        ];
+        let options = MmlOptions::default();
+        let (mml, offsets) = generate_mml_with_offsets(&notes, 120, 4, &tempos, &options);
+        assert_eq!(mml, generate_mml_final(&notes, 120, 4, &tempos, &options));
+        assert_eq!(offsets.len(), notes.len(), "{mml}");
+        assert_eq!(offsets.last(), Some(&mml.len()));
+        for (k, &end) in offsets.iter().enumerate() {
+            let prefix = parse_mml(&mml[..end]).unwrap();
+            let last = prefix.notes.last().unwrap();
+            assert_eq!(last.end, notes[k].end, "{}번째 음: {}", k, &mml[..end]);
+        }
+    }
+
+    // 첫 템포가 노트 중간에 걸려도 그 이후의 템포 변경까지 모두 출력되어야 한다(영구 차단 방지).
+    #[test]
+    fn multiple_tempo_changes_are_not_blocked() {
+        let notes = vec![note(60, 0, 1536)]; // C4, tick 0~1536
+        let tempos = vec![
+            TempoChange { tick: 0, bpm: 120 },
+            TempoChange { tick: 384, bpm: 100 },
+            TempoChange { tick: 768, bpm: 90 },
+        ];
+
+        let mml = generate_mml_final(&notes, 120, 4, &tempos, &MmlOptions::default());
+
+        assert!(mml.contains("T100"), "첫 노트 중간 템포 누락: {mml}");
+        assert!(mml.contains("T90"), "이후 템포가 차단됨: {mml}");
+    }
+
+    // 캡 분배: 보이스 수가 max_voices 를 절대 넘지 않아야 한다.
+    #[test]
+    fn capped_never_exceeds_max_voices() {
+        // 같은 시각에 8음 동시 타격
+        let mut notes = Vec::new();
+        for (i, p) in [48, 52, 55, 60, 64, 67, 72, 76].into_iter().enumerate() {
+            notes.push(note(p, 0, 384));
+            // 약간씩 다른 길이로 겹치게
+            notes.push(note(p, 384, 384 + i as u32 * 24));
+        }
+        for k in [1usize, 3, 6] {
+            let voices = allocate_voices_capped(notes.clone(), k);
+            assert!(voices.len() <= k, "k={k} 인데 보이스 {}개", voices.len());
+            // 각 보이스는 단음(시간 겹침 없음)이어야 한다.
+            for v in &voices {
+                for w in v.windows(2) {
+                    assert!(w[0].end <= w[1].start, "보이스 내 음이 겹침: {:?}", w);
+                }
+            }
+        }
+    }
+
+    // MIDI VLQ(가변 길이 수량) 인코딩
+    fn vlq(mut v: u32, out: &mut Vec<u8>) {
+        let mut stack = Vec::new();
+        stack.push((v & 0x7f) as u8);
+        v >>= 7;
+        while v > 0 {
+            stack.push(((v & 0x7f) as u8) | 0x80);
+            v >>= 7;
+        }
+        while let Some(b) = stack.pop() {
+            out.push(b);
+        }
+    }
+
+    // 템포 1개 + 노트 목록으로 최소 SMF(format 0) 바이트를 만든다. (테스트용)
+    // notes: (key, start_tick, dur_tick)
+    fn smf_bytes(tpb: u16, tempo_us: u32, notes: &[(u8, u32, u32)]) -> Vec<u8> {
+        let mut evts: Vec<(u32, [u8; 3])> = Vec::new();
+        for &(key, start, dur) in notes {
+            evts.push((start, [0x90, key, 100]));
+            evts.push((start + dur, [0x80, key, 0]));
+        }
+        smf_from_events(tpb, tempo_us, evts)
+    }
+
+    // 채널 메시지 목록 (절대 tick, 3바이트)
+    type Events = Vec<(u32, [u8; 3])>;
+
+    // 이름 붙은 트랙 여러 개로 format 1 SMF 바이트를 만든다.
+    fn smf_named_tracks(tpb: u16, tracks: &[(&str, Events)]) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(b"MThd");
+        out.extend_from_slice(&6u32.to_be_bytes());
+        out.extend_from_slice(&1u16.to_be_bytes()); // format 1
+        out.extend_from_slice(&(tracks.len() as u16).to_be_bytes());
+        out.extend_from_slice(&tpb.to_be_bytes());
+        for (name, evts) in tracks {
+            let mut track = Vec::new();
+            vlq(0, &mut track);
+            track.extend_from_slice(&[0xFF, 0x03, name.len() as u8]);
+            track.extend_from_slice(name.as_bytes());
+            let mut last = 0u32;
+            for &(tick, bytes) in evts {
+                vlq(tick - last, &mut track);
+                last = tick;
+                track.extend_from_slice(&bytes);
+            }
+            vlq(0, &mut track);
+            track.extend_from_slice(&[0xFF, 0x2F, 0x00]);
+            out.extend_from_slice(b"MTrk");
+            out.extend_from_slice(&(track.len() as u32).to_be_bytes());
+            out.extend_from_slice(&track);
+        }
+        out
+    }
+
+    // 템포 1개 + 채널 메시지 목록(절대 tick, 3바이트)으로 최소 SMF(format 0) 바이트를 만든다.
+    // 같은 tick 의 이벤트는 넣은 순서를 유지한다.
+    fn smf_from_events(tpb: u16, tempo_us: u32, mut evts: Vec<(u32, [u8; 3])>) -> Vec<u8> {
+        let mut track = Vec::new();
+        // tick 0 템포 메타
+        vlq(0, &mut track);
+        track.extend_from_slice(&[0xFF, 0x51, 0x03]);
+        track.push((tempo_us >> 16) as u8);
+        track.push((tempo_us >> 8) as u8);
+        track.push(tempo_us as u8);
+        // 채널 이벤트 (절대 tick 정렬)
+        evts.sort_by_key(|e| e.0);
+        let mut last = 0u32;
+        for (tick, bytes) in evts {
+            vlq(tick - last, &mut track);
+            last = tick;
+            track.extend_from_slice(&bytes);
+        }
+        // end of track
+        vlq(0, &mut track);
+        track.extend_from_slice(&[0xFF, 0x2F, 0x00]);
+
+        let mut out = Vec::new();
+        out.extend_from_slice(b"MThd");
+        out.extend_from_slice(&6u32.to_be_bytes());
+        out.extend_from_slice(&0u16.to_be_bytes()); // format 0
+        out.extend_from_slice(&1u16.to_be_bytes()); // 1 track
+        out.extend_from_slice(&tpb.to_be_bytes());
+        out.extend_from_slice(b"MTrk");
+        out.extend_from_slice(&(track.len() as u32).to_be_bytes());
+        out.extend_from_slice(&track);
+        out
+    }
+
+    // 255를 넘는 빠른 곡은 템포가 폴딩되고, 음표 길이도 같은 비율로 줄어 재생 시간이 보존돼야 한다.
+    #[test]
+    fn tempo_over_255_is_folded_preserving_walltime() {
+        // TPB 384, 340 BPM, 4분음표(384틱) 하나
+        let tempo_us = 60_000_000 / 340;
+        let midi = smf_bytes(384, tempo_us, &[(60, 0, 384)]);
+        let ExtractedMidi { notes, bpm, tempo_changes: tempos, .. } =
+            extract_midi_notes(&midi, &ExtractOptions::default()).unwrap();
+
+        assert!(bpm <= MML_TEMPO_MAX, "T가 상한을 넘음: {bpm}");
+        assert_eq!(bpm, 170, "340 BPM은 T170으로 접혀야 함: {bpm}");
+        assert_eq!(tempos[0].bpm, 170);
+        // 4분음표(384틱)가 8분음표(192틱)로 접혀야 함
+        assert_eq!(notes[0].duration, 192, "음표 길이가 절반으로 접혀야 함");
+
+        // 실제 재생 시간(wall-clock)이 원곡과 일치해야 한다 (1ms 이내)
+        let walltime = notes[0].duration as f64 / TPB as f64 / bpm as f64 * 60.0;
+        let original = 384.0 / 384.0 / 340.0 * 60.0;
+        assert!(
+            (walltime - original).abs() < 0.001,
+            "재생 시간 불일치: {walltime}s vs 원곡 {original}s"
+        );
+    }
+
+    // 조표(FF 59)가 있으면 그 조, 없으면 음 분포로 어림한 조를 돌려준다.
+    #[test]
+    fn key_from_signature_or_estimate() {
+        let midi = smf_bytes(384, 500_000, &[(70, 0, 384), (74, 384, 384), (77, 768, 384), (75, 1152, 384)]);
+        let ExtractedMidi { key, .. } =
+            extract_midi_notes(&midi, &ExtractOptions::default()).unwrap();
+        assert_eq!((key.fifths, key.minor, key.estimated), (-2, false, true), "B♭ 장조로 어림해야 함");
+
+        // 트랙 맨 앞에 D 단조 조표(플랫 1개) 메타를 끼운다
+        let mut signed = midi.clone();
+        signed.splice(22..22, [0x00, 0xFF, 0x59, 0x02, (-1i8) as u8, 0x01]);
+        let len = u32::from_be_bytes(signed[18..22].try_into().unwrap()) + 6;
+        signed[18..22].copy_from_slice(&len.to_be_bytes());
+        let ExtractedMidi { key, .. } =
+            extract_midi_notes(&signed, &ExtractOptions::default()).unwrap();
+        assert_eq!(key, Key::from_signature(-1, true));
+    }
+
+    // SMPTE 타임코드(25fps × 40서브프레임 = 초당 1000틱)는 가상 템포 120 의 박으로 옮긴다.
+    // 0.5초 = 4분음표. 파일 속 템포 메타는 SMPTE 에선 재생 속도와 무관하므로 무시한다.
+    #[test]
+    fn smpte_timecode_uses_derived_tempo() {
+        let division = u16::from_be_bytes([(-25i8) as u8, 40]);
+        let midi = smf_bytes(division, 60_000_000 / 200, &[(60, 500, 500), (64, 1000, 250)]);
+        let ExtractedMidi { notes, bpm, tempo_changes: tempos, .. } =
+            extract_midi_notes(&midi, &ExtractOptions::default()).unwrap();
+
+        assert_eq!(bpm, 120);
+        assert_eq!(tempos.len(), 1, "템포 메타는 무시: {tempos:?}");
+        assert_eq!((notes[0].start, notes[0].duration), (384, 384));
+        assert_eq!((notes[1].start, notes[1].duration), (768, 192));
+    }
+
+    // 255 이하의 정상 템포는 폴딩 없이 그대로 유지돼야 한다.
+    #[test]
+    fn tempo_under_255_is_unchanged() {
+        let tempo_us = 60_000_000 / 120;
+        let midi = smf_bytes(384, tempo_us, &[(60, 0, 384)]);
+        let ExtractedMidi { notes, bpm, .. } =
+            extract_midi_notes(&midi, &ExtractOptions::default()).unwrap();
+        assert_eq!(bpm, 120, "정상 템포는 그대로여야 함");
+        assert_eq!(notes[0].duration, 384, "정상 템포에선 음표 길이 불변");
+    }
+
+    // 서스테인 페달(CC64)을 밟은 동안 뗀 건반은 페달을 뗄 때까지 울려야 한다.
+    #[test]
+    fn sustain_pedal_extends_released_notes() {
+        let midi = smf_from_events(
+            384,
+            500_000,
+            vec![
+                (0, [0xB0, 64, 127]),   // 페달 밟음
+                (0, [0x90, 60, 100]),   // C4
+                (96, [0x80, 60, 0]),    // 16분음표 길이로 건반 뗌
+                (192, [0x90, 64, 100]), // E4
+                (288, [0x80, 64, 0]),
+                (768, [0xB0, 64, 0]),   // 2분음표 지점에서 페달 뗌
+                (768, [0x90, 67, 100]), // 페달 뗀 뒤의 G4 는 그대로
+                (864, [0x80, 67, 0]),
+            ],
+        );
+        let ExtractedMidi { notes, .. } =
+            extract_midi_notes(&midi, &ExtractOptions::default()).unwrap();
+        let end_of = |key: u8| notes.iter().find(|n| n.note == key).unwrap().end;
+        assert_eq!(end_of(60), 768, "페달로 붙잡힌 음이 페달 해제 시점까지 가야 함");
+        assert_eq!(end_of(64), 768);
+        assert_eq!(end_of(67), 864, "페달 해제 뒤 음은 원래 길이");
+    }
+
+    // 페달로 울리던 음을 다시 치면 이전 음은 그 자리에서 끝나고 새 음이 시작된다.
+    #[test]
+    fn sustain_pedal_retrigger_ends_previous_note() {
+        let midi = smf_from_events(
+            384,
+            500_000,
+            vec![
+                (0, [0xB0, 64, 127]),
+                (0, [0x90, 60, 100]),
+                (96, [0x80, 60, 0]),
+                (384, [0x90, 60, 100]), // 같은 음 다시 침
+                (480, [0x80, 60, 0]),
+                (768, [0xB0, 64, 0]),
+            ],
+        );
+        let ExtractedMidi { notes, .. } =
+            extract_midi_notes(&midi, &ExtractOptions::default()).unwrap();
+        let spans: Vec<(u32, u32)> = notes.iter().map(|n| (n.start, n.end)).collect();
+        assert_eq!(spans, vec![(0, 384), (384, 768)]);
+    }
+
+    // 소스테누토(CC66)는 옵션일 때만, 밟는 순간 눌려 있던 음만 붙잡는다.
+    #[test]
+    fn sostenuto_pedal_holds_only_captured_notes() {
+        let midi = smf_from_events(
+            384,
+            500_000,
+            vec![
+                (0, [0x90, 48, 100]),   // C3 누른 채로
+                (48, [0xB0, 66, 127]),  // 소스테누토 밟음 → C3 만 붙잡힘
+                (96, [0x80, 48, 0]),
+                (96, [0x90, 72, 100]),  // 밟은 뒤 친 C5 는 안 붙잡힘
+                (192, [0x80, 72, 0]),
+                (768, [0xB0, 66, 0]),
+            ],
+        );
+        let end_of = |notes: &[Note], key: u8| notes.iter().find(|n| n.note == key).unwrap().end;
+
+        let ExtractedMidi { notes: plain, .. } =
+            extract_midi_notes(&midi, &ExtractOptions::default()).unwrap();
+        assert_eq!(end_of(&plain, 48), 96, "옵션이 꺼져 있으면 CC66 무시");
+
+        let options = ExtractOptions {
+            sostenuto: true,
+            ..Default::default()
+        };
+        let ExtractedMidi { notes, .. } = extract_midi_notes(&midi, &options).unwrap();
+        assert_eq!(end_of(&notes, 48), 768, "밟는 순간 눌려 있던 음은 해제까지 유지");
+        assert_eq!(end_of(&notes, 72), 192, "밟은 뒤 친 음은 원래 길이");
+    }
+
+    // 피치 벤드: sustain 은 가장 오래 머문 음높이 하나로, 벤드 범위(RPN 0)를 따른다.
+    #[test]
+    fn pitch_bend_snaps_to_sustained_pitch() {
+        let bend_up_two = [0xE0, 0x7F, 0x7F]; // 최대 벤드 = +2반음 (기본 범위)
+        let midi = smf_from_events(
+            384,
+            500_000,
+            vec![(0, [0x90, 60, 100]), (96, bend_up_two), (1536, [0x80, 60, 0])],
+        );
+        let keys = |options: &ExtractOptions| -> Vec<u8> {
+            extract_midi_notes(&midi, options).unwrap().notes.iter().map(|n| n.note).collect()
+        };
+        assert_eq!(keys(&ExtractOptions::default()), vec![60], "기본은 벤드 무시");
+        let sustain = ExtractOptions {
+            pitch_bend: PitchBendMode::Sustain,
+            ..Default::default()
+        };
+        assert_eq!(keys(&sustain), vec![62], "밴딩해 올린 음높이로");
+
+        // 벤드 범위를 12반음으로 바꾼 뒤 절반 벤드 = +6반음
+        let wide = smf_from_events(
+            384,
+            500_000,
+            vec![
+                (0, [0xB0, 101, 0]),
+                (0, [0xB0, 100, 0]),
+                (0, [0xB0, 6, 12]),
+                (0, [0xE0, 0x00, 0x60]),
+                (0, [0x90, 60, 100]),
+                (384, [0x80, 60, 0]),
+            ],
+        );
+        let ExtractedMidi { notes, .. } = extract_midi_notes(&wide, &sustain).unwrap();
+        assert_eq!(notes[0].note, 66);
+    }
+
+    // 피치 벤드 steps: 반음을 넘는 글라이드는 반음 계단으로, 격자보다 짧게 지나간 반음은 건너뛴다.
+    #[test]
+    fn pitch_bend_glide_becomes_stepped_run() {
+        let steps = ExtractOptions {
+            pitch_bend: PitchBendMode::Steps,
+            ..Default::default()
+        };
+        let run = |bends: &[(u32, [u8; 3])]| -> Vec<(u8, u32, u32)> {
+            let mut events = vec![(0, [0x90, 60, 100]), (1152, [0x80, 60, 0])];
+            events.extend_from_slice(bends);
+            let midi = smf_from_events(384, 500_000, events);
+            let ExtractedMidi { notes, .. } = extract_midi_notes(&midi, &steps).unwrap();
+            notes.iter().map(|n| (n.note, n.start, n.end)).collect()
+        };
+        let half = [0xE0, 0x00, 0x60]; // +1반음
+        let full = [0xE0, 0x7F, 0x7F]; // +2반음
+        assert_eq!(
+            run(&[(384, half), (768, full)]),
+            vec![(60, 0, 384), (61, 384, 768), (62, 768, 1152)]
+        );
+        assert_eq!(run(&[(384, half), (390, full)]), vec![(60, 0, 384), (62, 384, 1152)]);
+    }
+
+    // 셋잇단 8분음표(128틱)는 일반 그리드(120/144)로 뭉개지지 않고 셋잇단 길이로 나와야 한다.
+    #[test]
+    fn triplet_eighths_use_triplet_grid_and_lengths() {
+        // 1박에 셋잇단 8분 3개 + 다음 박은 일반 8분 2개 (사람 연주처럼 2~3틱 흔들림)
+        let midi = smf_bytes(
+            384,
+            500_000,
+            &[(60, 0, 128), (62, 130, 126), (64, 255, 129), (65, 384, 192), (67, 577, 191)],
+        );
+        let ExtractedMidi { notes, bpm, tempo_changes: tempos, .. } =
+            extract_midi_notes(&midi, &ExtractOptions::default()).unwrap();
+        let starts: Vec<u32> = notes.iter().map(|n| n.start).collect();
+        assert_eq!(starts, vec![0, 128, 256, 384, 576], "셋잇단 박만 32틱 그리드로 스냅");
+        assert_eq!(notes[1].duration, 128);
+
+        let mml = generate_mml_final(&notes, bpm, 4, &tempos, &MmlOptions::default());
+        assert!(mml.contains("C12") || mml.contains("L12"), "셋잇단 길이(12)가 안 쓰임: {mml}");
+        assert!(!mml.contains('&'), "셋잇단이 타이로 근사됨: {mml}");
+    }
+
+    // 셋잇단 박과 일반 박에 걸친 길이도 자투리 없이 정확히 적혀야 한다 (드리프트 방지).
+    #[test]
+    fn mixed_triplet_lengths_are_exact() {
+        let ex = get_exact_lengths();
+        for ticks in [56u32, 88, 152, 280, 440, 1000] {
+            let sum: u32 = find_tie_combination(ticks, None, &ex).iter().map(|(_, t)| t).sum();
+            assert_eq!(sum, ticks, "ticks={ticks} 타이 분할 합 불일치");
+
+            let approx: u32 = find_safe_approximation(ticks, &ex).iter().map(|(_, t)| t).sum();
+            assert!(approx <= ticks);
+            let rest = ticks - approx;
+            let lengths = family_lengths(rest, &ex);
+            assert!(is_representable(rest, &lengths), "ticks={ticks} 근사 뒤 남은 {rest}틱을 못 적음");
+        }
+    }
+
+    // 일반 박 안의 음은 셋잇단 길이를 쓰지 않아 기존 출력과 같아야 한다.
+    #[test]
+    fn straight_lengths_ignore_triplet_table() {
+        let ex = get_exact_lengths();
+        let ties: Vec<String> = find_tie_combination(264, None, &ex).into_iter().map(|(s, _)| s).collect();
+        assert_eq!(ties, vec!["8", "32."]);
+        assert_eq!(find_safe_approximation(264, &ex)[0].1, 192);
+    }
+
+    // 셈여림: 히스테리시스 이상 바뀔 때만 V 를 적고, 옵션이 꺼져 있으면 V15 고정.
+    #[test]
+    fn dynamics_emit_volume_with_hysteresis() {
+        let vel = |v: u8, start: u32| Note {
+            velocity: v,
+            ..note(60, start, 192)
+        };
+        // V15 → V14(1단계, 무시) → V7 → V8(1단계, 무시)
+        let notes = vec![vel(127, 0), vel(119, 192), vel(60, 384), vel(64, 576)];
+
+        let fixed = generate_mml_final(&notes, 120, 4, &[], &MmlOptions::default());
+        assert!(fixed.starts_with("T120V15O4"), "옵션 없으면 V15 고정: {fixed}");
+        assert_eq!(fixed.matches('V').count(), 1);
+
+        let options = MmlOptions {
+            dynamics: Some(2),
+            ..Default::default()
+        };
+        let mml = generate_mml_final(&notes, 120, 4, &[], &options);
+        assert!(mml.starts_with("T120V15O4"), "첫 음 음량으로 시작: {mml}");
+        assert_eq!(mml.matches('V').count(), 2, "큰 변화만 V 로 적어야 함: {mml}");
+        assert!(mml.contains("V7"), "셈여림 변화 누락: {mml}");
+    }
+
+    // 채널 볼륨(CC7)·익스프레션(CC11)은 음 시작 시점 값이 그 음의 음량이 된다.
+    #[test]
+    fn channel_volume_and_expression_scale_note_volume() {
+        let midi = smf_from_events(
+            384,
+            500_000,
+            vec![
+                (0, [0xB0, 7, 127]),
+                (0, [0xB0, 11, 127]),
+                (0, [0x90, 60, 100]),
+                (192, [0x80, 60, 0]),
+                (192, [0xB0, 7, 64]),  // 볼륨 절반
+                (192, [0xB0, 11, 64]), // 익스프레션 절반
+                (192, [0x90, 62, 100]),
+                (384, [0x80, 62, 0]),
+            ],
+        );
+        let ExtractedMidi { notes, .. } =
+            extract_midi_notes(&midi, &ExtractOptions::default()).unwrap();
+        assert_eq!(notes[0].volume, 127);
+        assert_eq!(notes[1].volume, 32, "볼륨 × 익스프레션");
+        assert!(mml_volume(&notes[1]) < mml_volume(&notes[0]));
+    }
+
+    // 멀티악기 샘플로 악기 인지 분배 결과 확인 (cargo test explore_instruments -- --ignored --nocapture)
+    #[test]
+    #[ignore]
+    fn explore_instruments() {
+        use crate::utils::mml::gm_family_name;
+        let path = "../sample/jin-jino-ju-ren-hong-lianno-gong-shishort-ver.mid";
+        let midi = std::fs::read(path).unwrap();
+        let ExtractedMidi { notes, bpm, tempo_changes, .. } =
+            extract_midi_notes(&midi, &ExtractOptions::default()).unwrap();
+        println!("\n{path}\n총 노트: {}, BPM: {}", notes.len(), bpm);
+
+        let voices = allocate_voices_by_instrument(notes.clone(), 6);
+        let kept: usize = voices.iter().map(|v| v.len()).sum();
+        println!(
+            "\n[최대 6보이스] {}개, 보존 {:.1}%",
+            voices.len(),
+            100.0 * kept as f64 / notes.len() as f64
+        );
+        for v in &voices {
+            let prog = v[0].program;
+            let oct = ((v[0].note as i32 / 12) - 1).clamp(2, 6);
+            let mml = generate_mml_final(v, bpm, oct, &tempo_changes, &MmlOptions::default());
+            let mml_len = mml.len();
+            println!(
+                "   {} (prog{}): {}음, MML {}자",
+                gm_family_name(prog),
+                prog,
+                v.len(),
+                mml_len
+            );
+        }
+    }
+
+    // 악기 인지 분배: 합주 시 각 악기가 적어도 하나의 보이스로 대표되어야 한다.
+    #[test]
+    fn ensemble_represents_each_instrument() {
+        // 피아노(prog0) 화음 + 기타(prog24) 단선 + 플룻(prog73) 단선
+        let mut notes = Vec::new();
+        for t in 0..8u32 {
+            // 피아노: 3음 화음
+            notes.push(note_prog(60, t * 384, 384, 0));
+            notes.push(note_prog(64, t * 384, 384, 0));
+            notes.push(note_prog(67, t * 384, 384, 0));
+            // 기타
+            notes.push(note_prog(52, t * 384, 384, 24));
+            // 플룻
+            notes.push(note_prog(79, t * 384, 384, 73));
+        }
+        let voices = allocate_voices_by_instrument(notes, 6);
+        let programs: std::collections::HashSet<u8> =
+            voices.iter().map(|v| v[0].program).collect();
+        assert!(programs.contains(&0), "피아노가 대표되어야 함");
+        assert!(programs.contains(&24), "기타가 대표되어야 함");
+        assert!(programs.contains(&73), "플룻이 대표되어야 함");
+    }
+
+    // 베이스 분리: 화음의 바닥음만 베이스로 가고, 단선율/상성부는 나머지로 간다.
+    #[test]
+    fn split_bass_line_separates_chord_bottom() {
+        let notes = vec![
+            // 동시 3음 화음 (48 바닥 / 60 중간 / 72 위)
+            note(48, 0, 384),
+            note(60, 0, 384),
+            note(72, 0, 384),
+            // 단선율 멜로디 음 (혼자 울림)
+            note(67, 384, 384),
+        ];
+        let (bass, rest) = split_bass_line(notes);
+        // 바닥음 48만 베이스
+        assert_eq!(bass.len(), 1, "베이스는 화음 바닥음 1개여야: {:?}", bass);
+        assert_eq!(bass[0].note, 48);
+        // 나머지(60, 72, 단선율 67)는 rest
+        assert!(rest.iter().any(|n| n.note == 67), "단선율 멜로디는 베이스가 아님");
+        assert_eq!(rest.len(), 3);
+    }
+
+    // 고정 멜로디: 지정한 음만 단음 한 줄로 (겹치면 꼬리 자름, 동시 시작이면 높은 음), 나머지는 돌려보냄.
+    #[test]
+    fn split_melody_line_keeps_pinned_notes_monophonic() {
+        let pinned = |n: Note| Note { track: 1, ..n };
+        let notes = vec![
+            note(84, 0, 768), // 멜로디보다 높은 패드 (다른 트랙)
+            pinned(note(67, 0, 384)),
+            pinned(note(64, 0, 384)), // 동시 시작 → 낮은 쪽은 나머지로
+            pinned(note(69, 192, 384)), // 앞 음 꼬리를 자름
+        ];
+        let (melody, rest) = split_melody_line(notes, |n| n.track == 1);
+        let spans: Vec<(u8, u32, u32)> = melody.iter().map(|n| (n.note, n.start, n.end)).collect();
+        assert_eq!(spans, vec![(67, 0, 192), (69, 192, 576)]);
+        let mut rest: Vec<u8> = rest.iter().map(|n| n.note).collect();
+        rest.sort_unstable();
+        assert_eq!(rest, vec![64, 84]);
+    }
+
+    // 고음 길이 근사는 원래 길이를 절대 넘지 않아야 한다 (넘으면 타이밍이 밀림 = 드리프트).
+    #[test]
+    fn safe_approximation_never_overshoots() {
+        let ex = get_exact_lengths();
+        // 정확히 떨어지지 않는 길이들(24의 배수지만 정확 길이표에 없는 값들)
+        for ticks in [120u32, 168, 216, 240, 264, 312, 360, 1000, 2280] {
+            let r = find_safe_approximation(ticks, &ex);
+            let sum: u32 = r.iter().map(|(_, t)| t).sum();
+            assert!(sum <= ticks, "ticks={ticks} 인데 근사 합 {sum} 으로 초과(드리프트)");
+        }
+    }
+
+    // 범위 밖 음은 옥타브 단위로만 옮겨 음이름을 지킨다 (베이스 O1~O4: 72=C5 → 60=C4, 14=D0 → 26=D1).
+    #[test]
+    fn fold_octaves_keeps_pitch_class() {
+        let notes = vec![note(72, 0, 96), note(14, 96, 96), note(50, 192, 96), note(95, 288, 96)];
+        let folded = fold_octaves(notes, OctaveRange { min: 1, max: 4 });
+        let pitches: Vec<u8> = folded.iter().map(|n| n.note).collect();
+        assert_eq!(pitches, vec![60, 26, 50, 71]);
+
+        // 범위를 MML 유효 범위 밖으로 줘도 O1~O8 안으로 접힌다
+        let wide = fold_octaves(vec![note(5, 0, 96), note(127, 96, 96)], OctaveRange { min: -3, max: 12 });
+        assert!(wide.iter().all(|n| mml_octave(n.note).1), "{:?}", wide);
+    }
+
+    // 아주 낮은/높은 음이 섞여도 MML 옥타브가 유효 범위(O1~O8)를 벗어나면 안 된다.
+    #[test]
+    fn mml_octave_stays_in_valid_range() {
+        let notes = vec![
+            note(5, 0, 384),     // 매우 낮은 음 (raw 옥타브 -1)
+            note(20, 384, 384),  // 낮은 음 (raw 옥타브 0)
+            note(120, 768, 384), // 매우 높은 음 (raw 옥타브 9)
+        ];
+        let mml = generate_mml_final(&notes, 120, 4, &[], &MmlOptions::default());
+        assert!(!mml.contains("O-"), "음수 옥타브가 생성됨: {mml}");
+        assert!(!mml.contains("O0"), "O0이 생성됨: {mml}");
+        assert!(!mml.contains("O9"), "O9가 생성됨: {mml}");
+    }
+
+    // 캡 분배: 멜로디(최고음)와 베이스(최저음)는 보호되어야 한다.
+    #[test]
+    fn capped_preserves_melody_and_bass() {
+        // 6음 동시(40~84), 멜로디=84 베이스=40, max_voices=3 으로 강하게 압축
+        let pitches = [40u8, 50, 60, 67, 74, 84];
+        let notes: Vec<Note> = pitches.iter().map(|&p| note(p, 0, 768)).collect();
+        let voices = allocate_voices_capped(notes, 3);
+
+        let kept: Vec<u8> = voices.iter().flat_map(|v| v.iter()).map(|n| n.note).collect();
+        assert!(kept.contains(&84), "멜로디(최고음)가 보존되어야 함: {kept:?}");
+        assert!(kept.contains(&40), "베이스(최저음)가 보존되어야 함: {kept:?}");
+        // 평균 음높이 내림차순 정렬: 첫 보이스 평균 >= 마지막 보이스 평균
+        if voices.len() >= 2 {
+            assert!(avg_pitch(&voices[0]) >= avg_pitch(voices.last().unwrap()));
+        }
+    }
+
+    // 음을 못 뽑는 경우는 code 로 구분되는 오류가 된다 (빈 곡 / 드럼만 있는 곡 / MIDI 가 아님).
+    #[test]
+    fn extract_reports_typed_errors() {
+        let opts = ExtractOptions::default();
+        let empty = smf_from_events(480, 500_000, vec![]);
+        assert_eq!(extract_midi_notes(&empty, &opts).unwrap_err(), ConversionError::NoNotes);
+
+        let drums = smf_from_events(
+            480,
+            500_000,
+            vec![
+                (0, [0x99, 36, 100]),
+                (240, [0x89, 36, 0]),
+                (240, [0x99, 38, 100]),
+                (480, [0x89, 38, 0]),
+            ],
+        );
+        assert_eq!(
+            extract_midi_notes(&drums, &opts).unwrap_err(),
+            ConversionError::DrumOnly { drum_notes: 2 }
+        );
+
+        let err = extract_midi_notes(b"not a midi file", &opts).unwrap_err();
+        assert!(matches!(err, ConversionError::InvalidMidi { offset: 0, .. }), "{err:?}");
+    }
+
+    // GS 파트 설정 SysEx(지휘 트랙)나 뱅크 MSB 127 로 드럼이 된 채널의 음은 멜로디에 섞이지 않고,
+    // 리듬 파트를 만들 때만 드럼 음으로 뽑힌다.
+    #[test]
+    fn gs_and_xg_drum_parts_are_classified() {
+        use midly::{Format, Header, MidiMessage, Smf, Timing, TrackEvent, TrackEventKind};
+
+        let gs_part_2 = [0x41, 0x10, 0x42, 0x12, 0x40, 0x12, 0x15, 0x01, 0x18, 0xF7];
+        let event = |delta: u32, ch: u8, message: MidiMessage| TrackEvent {
+            delta: delta.into(),
+            kind: TrackEventKind::Midi { channel: ch.into(), message },
+        };
+        let on = |key: u8| MidiMessage::NoteOn { key: key.into(), vel: 100.into() };
+        let off = |key: u8| MidiMessage::NoteOff { key: key.into(), vel: 0.into() };
+        let mut smf = Smf::new(Header::new(Format::Parallel, Timing::Metrical(480.into())));
+        smf.tracks.push(vec![TrackEvent { delta: 0.into(), kind: TrackEventKind::SysEx(&gs_part_2) }]);
+        smf.tracks.push(vec![
+            event(0, 0, on(72)),
+            event(0, 1, on(36)),
+            event(0, 2, MidiMessage::Controller { controller: 0.into(), value: 127.into() }),
+            event(0, 2, MidiMessage::ProgramChange { program: 0.into() }),
+            event(0, 2, on(42)),
+            event(240, 1, off(36)),
+            event(0, 2, off(42)),
+            event(240, 0, off(72)),
+        ]);
+        let mut bytes = Vec::new();
+        smf.write_std(&mut bytes).unwrap();
+
+        let ExtractedMidi { notes, .. } =
+            extract_midi_notes(&bytes, &ExtractOptions::default()).unwrap();
+        assert_eq!(notes.iter().map(|n| n.note).collect::<Vec<_>>(), vec![72]);
+
+        let with_drums = ExtractOptions { percussion: true, ..ExtractOptions::default() };
+        let ExtractedMidi { notes, .. } = extract_midi_notes(&bytes, &with_drums).unwrap();
+        let mut drums: Vec<(u8, u8)> =
+            notes.iter().filter(|n| n.percussion).map(|n| (n.channel, n.note)).collect();
+        drums.sort_unstable();
+        assert_eq!(drums, vec![(1, 36), (2, 42)]);
+    }
+
+    // 음마다 트랙 번호·이름·채널이 붙고, SourceFilter 로 트랙·채널을 넣고 뺄 수 있다.
+    #[test]
+    fn notes_carry_source_and_can_be_filtered() {
+        let midi = smf_named_tracks(
+            480,
+            &[
+                ("Vocal", vec![(0, [0x90, 72, 100]), (480, [0x80, 72, 0])]),
+                (
+                    "Pad",
+                    vec![
+                        (0, [0x91, 60, 100]),
+                        (0, [0x92, 48, 100]),
+                        (960, [0x81, 60, 0]),
+                        (960, [0x82, 48, 0]),
+                    ],
+                ),
+            ],
+        );
+        let ExtractedMidi { notes, .. } =
+            extract_midi_notes(&midi, &ExtractOptions::default()).unwrap();
+        let sources: Vec<(u8, usize, Option<&str>, u8)> = notes
+            .iter()
+            .map(|n| (n.note, n.track, n.track_name.as_deref(), n.channel))
+            .collect();
+        assert_eq!(
+            sources,
+            vec![(72, 0, Some("Vocal"), 0), (60, 1, Some("Pad"), 1), (48, 1, Some("Pad"), 2)]
+        );
+
+        let pitches = |source: SourceFilter| -> Vec<u8> {
+            let options = ExtractOptions {
+                source,
+                ..Default::default()
+            };
+            extract_midi_notes(&midi, &options).unwrap().notes.iter().map(|n| n.note).collect()
+        };
+        let only_vocal = SourceFilter {
+            include_tracks: vec![0],
+            ..Default::default()
+        };
+        assert_eq!(pitches(only_vocal), vec![72]);
+        let no_ch2 = SourceFilter {
+            exclude_channels: vec![2],
+            ..Default::default()
+        };
+        assert_eq!(pitches(no_ch2), vec![72, 60]);
+        let pad_but_ch1 = SourceFilter {
+            include_tracks: vec![1],
+            exclude_channels: vec![1],
+            ..Default::default()
+        };
+        assert_eq!(pitches(pad_but_ch1), vec![48]);
+
+        let nothing = ExtractOptions {
+            source: SourceFilter {
+                exclude_tracks: vec![0, 1],
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert_eq!(extract_midi_notes(&midi, &nothing).unwrap_err(), ConversionError::NoNotes);
+    }
+}
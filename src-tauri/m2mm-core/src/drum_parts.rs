@@ -0,0 +1,219 @@
+// 드럼 파트 지도: 어느 채널이 언제 드럼(리듬) 파트인지 알려 준다.
+// GM 은 10번 채널만 드럼이지만, GS/XG 파일은 뱅크 셀렉트 MSB(120/127)나 파트 설정 SysEx 로
+// 다른 채널에도 드럼 세트를 둔다. 그런 채널의 음이 멜로디 보이스에 섞이지 않게 구분한다.
+
+use midly::{MidiMessage, TrackEvent, TrackEventKind};
+
+use crate::converter::DRUM_CHANNEL;
+
+// 뱅크 셀렉트 MSB 컨트롤 번호와 드럼 세트 뱅크 (GM2 리듬 = 120, XG 드럼 = 127)
+const CC_BANK_SELECT_MSB: u8 = 0;
+const DRUM_BANKS: [u8; 2] = [120, 127];
+
+// 제조사 ID (SysEx 첫 바이트)
+const ROLAND: u8 = 0x41;
+const YAMAHA: u8 = 0x43;
+const UNIVERSAL_NON_REALTIME: u8 = 0x7E;
+
+// 드럼 파트에 관한 SysEx
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SysEx {
+    Reset, // GM System On / GS Reset / XG System On (10번 채널만 드럼으로)
+    RhythmPart(u8, bool), // 채널을 드럼 파트로 쓰는지 (GS Use for Rhythm Part, XG Part Mode)
+}
+
+// 시각순으로 다시 돌려 볼 채널 이벤트
+#[derive(Debug, Clone, Copy)]
+enum PartEvent {
+    BankSelect(u8, u8),
+    ProgramChange(u8),
+    SysEx(SysEx),
+}
+
+/// 채널별 드럼 파트 변경 기록. 틱 단위는 원본 MIDI 틱.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct DrumParts {
+    changes: [Vec<(u32, bool)>; 16], // 채널마다 (틱, 드럼인지), 시각순
+}
+
+impl DrumParts {
+    /// 모든 트랙의 뱅크 셀렉트·프로그램 체인지·SysEx 를 시각순으로 따라가 지도를 만든다.
+    /// 뱅크는 다음 프로그램 체인지에서 적용되고, 10번 채널은 SysEx 로만 드럼을 끈다.
+    pub fn from_tracks(tracks: &[Vec<TrackEvent>]) -> Self {
+        let mut events: Vec<(u32, PartEvent)> = Vec::new();
+        for track in tracks {
+            let mut tick = 0u32;
+            for event in track {
+                tick += event.delta.as_int();
+                let part_event = match event.kind {
+                    TrackEventKind::Midi { channel, message } => match message {
+                        MidiMessage::Controller { controller, value }
+                            if controller.as_int() == CC_BANK_SELECT_MSB =>
+                        {
+                            PartEvent::BankSelect(channel.as_int(), value.as_int())
+                        }
+                        MidiMessage::ProgramChange { .. } => {
+                            PartEvent::ProgramChange(channel.as_int())
+                        }
+                        _ => continue,
+                    },
+                    TrackEventKind::SysEx(data) => match parse_sysex(data) {
+                        Some(sysex) => PartEvent::SysEx(sysex),
+                        None => continue,
+                    },
+                    _ => continue,
+                };
+                events.push((tick, part_event));
+            }
+        }
+        events.sort_by_key(|&(tick, _)| tick); // 안정 정렬: 같은 틱은 트랙 순서대로
+
+        let mut parts = DrumParts::default();
+        let mut bank: [Option<u8>; 16] = [None; 16];
+        let mut by_bank = [false; 16]; // 뱅크로 드럼이 된 채널 (다른 뱅크를 고르면 되돌린다)
+        for (tick, event) in events {
+            match event {
+                PartEvent::BankSelect(ch, value) => bank[ch as usize] = Some(value),
+                PartEvent::ProgramChange(ch) => {
+                    let chi = ch as usize;
+                    match bank[chi] {
+                        Some(b) if DRUM_BANKS.contains(&b) => {
+                            by_bank[chi] = true;
+                            parts.set(ch, tick, true);
+                        }
+                        Some(_) if by_bank[chi] => {
+                            by_bank[chi] = false;
+                            parts.set(ch, tick, false);
+                        }
+                        _ => {}
+                    }
+                }
+                PartEvent::SysEx(SysEx::RhythmPart(ch, drum)) => {
+                    by_bank[ch as usize] = false;
+                    parts.set(ch, tick, drum);
+                }
+                PartEvent::SysEx(SysEx::Reset) => {
+                    bank = [None; 16];
+                    by_bank = [false; 16];
+                    for ch in 0..16 {
+                        parts.set(ch, tick, ch == DRUM_CHANNEL);
+                    }
+                }
+            }
+        }
+        parts
+    }
+
+    /// tick 시점에 ch 채널이 드럼 파트인지
+    pub fn is_drum(&self, ch: u8, tick: u32) -> bool {
+        let changes = &self.changes[ch as usize];
+        match changes.partition_point(|&(t, _)| t <= tick) {
+            0 => ch == DRUM_CHANNEL,
+            i => changes[i - 1].1,
+        }
+    }
+
+    // 바뀐 때만 기록한다
+    fn set(&mut self, ch: u8, tick: u32, drum: bool) {
+        if self.is_drum(ch, tick) != drum {
+            self.changes[ch as usize].push((tick, drum));
+        }
+    }
+}
+
+// 드럼 파트에 관한 SysEx 만 읽는다 (midly 는 앞의 F0 를 떼고 준다)
+fn parse_sysex(data: &[u8]) -> Option<SysEx> {
+    match *data {
+        // GM System On/Off (7E dev 09 0x)
+        [UNIVERSAL_NON_REALTIME, _, 0x09, ..] => Some(SysEx::Reset),
+        // GS Reset (41 dev 42 12 40 00 7F 00)
+        [ROLAND, _, 0x42, 0x12, 0x40, 0x00, 0x7F, 0x00, ..] => Some(SysEx::Reset),
+        // GS Use for Rhythm Part (41 dev 42 12 40 1x 15 vv): x 는 파트 블록, vv 0 = 끔, 1·2 = 드럼 맵
+        [ROLAND, _, 0x42, 0x12, 0x40, block, 0x15, value, ..] if block & 0xF0 == 0x10 => {
+            Some(SysEx::RhythmPart(gs_block_channel(block & 0x0F), value != 0))
+        }
+        // XG System On (43 1n 4C 00 00 7E 00)
+        [YAMAHA, device, 0x4C, 0x00, 0x00, 0x7E, 0x00, ..] if device & 0xF0 == 0x10 => {
+            Some(SysEx::Reset)
+        }
+        // XG Part Mode (43 1n 4C 08 pp 07 vv): vv 0 = 일반, 1 이상 = 드럼
+        [YAMAHA, device, 0x4C, 0x08, part, 0x07, value, ..]
+            if device & 0xF0 == 0x10 && part < 16 =>
+        {
+            Some(SysEx::RhythmPart(part, value != 0))
+        }
+        _ => None,
+    }
+}
+
+// GS 파트 블록 번호 → 채널. 블록 0 이 10번 파트, 1~9 가 1~9번, A~F 가 11~16번 파트.
+fn gs_block_channel(block: u8) -> u8 {
+    match block {
+        0 => DRUM_CHANNEL,
+        1..=9 => block - 1,
+        _ => block,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use midly::num::{u4, u7};
+
+    fn at(delta: u32, kind: TrackEventKind<'_>) -> TrackEvent<'_> {
+        TrackEvent { delta: delta.into(), kind }
+    }
+
+    fn midi(delta: u32, ch: u8, message: MidiMessage) -> TrackEvent<'static> {
+        at(delta, TrackEventKind::Midi { channel: u4::new(ch), message })
+    }
+
+    fn bank(delta: u32, ch: u8, value: u8) -> TrackEvent<'static> {
+        let (controller, value) = (u7::new(CC_BANK_SELECT_MSB), u7::new(value));
+        midi(delta, ch, MidiMessage::Controller { controller, value })
+    }
+
+    fn program(delta: u32, ch: u8) -> TrackEvent<'static> {
+        midi(delta, ch, MidiMessage::ProgramChange { program: u7::new(0) })
+    }
+
+    // 뱅크 MSB 127 은 프로그램 체인지부터 드럼, 일반 뱅크로 바꾸면 다시 멜로디. 10번 채널은 그대로 드럼.
+    #[test]
+    fn bank_select_applies_at_program_change() {
+        let track = vec![
+            bank(0, 1, 127),
+            program(100, 1),
+            bank(0, 9, 0),
+            program(0, 9),
+            bank(100, 1, 0),
+            program(0, 1),
+        ];
+        let parts = DrumParts::from_tracks(&[track]);
+        assert!(!parts.is_drum(1, 50), "뱅크만 고르고 프로그램 체인지 전");
+        assert!(parts.is_drum(1, 100));
+        assert!(!parts.is_drum(1, 200));
+        assert!(parts.is_drum(9, 200));
+        assert!(!parts.is_drum(0, 0));
+    }
+
+    // GS 파트 설정은 다른 트랙(지휘 트랙)에 있어도 그 시점부터 채널에 적용되고, GS Reset 이 되돌린다.
+    #[test]
+    fn gs_and_xg_sysex_set_rhythm_parts() {
+        let gs_part_2 = [0x41, 0x10, 0x42, 0x12, 0x40, 0x12, 0x15, 0x01, 0x18, 0xF7];
+        let gs_part_10_off = [0x41, 0x10, 0x42, 0x12, 0x40, 0x10, 0x15, 0x00, 0x1B, 0xF7];
+        let xg_part_4 = [0x43, 0x10, 0x4C, 0x08, 0x03, 0x07, 0x01, 0xF7];
+        let gs_reset = [0x41, 0x10, 0x42, 0x12, 0x40, 0x00, 0x7F, 0x00, 0x41, 0xF7];
+        let conductor = vec![
+            at(0, TrackEventKind::SysEx(&gs_part_2)),
+            at(0, TrackEventKind::SysEx(&gs_part_10_off)),
+            at(0, TrackEventKind::SysEx(&xg_part_4)),
+            at(1000, TrackEventKind::SysEx(&gs_reset)),
+        ];
+        let parts = DrumParts::from_tracks(&[conductor, vec![program(10, 1)]]);
+        assert!(parts.is_drum(1, 0), "GS 블록 2 = 2번 파트 = 채널 1");
+        assert!(!parts.is_drum(9, 0));
+        assert!(parts.is_drum(3, 500), "XG 파트 3 = 채널 3");
+        assert!(!parts.is_drum(1, 1000));
+        assert!(parts.is_drum(9, 1000));
+    }
+}
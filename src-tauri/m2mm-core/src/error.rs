@@ -0,0 +1,107 @@
+// 변환 엔진의 오류 타입.
+// 직렬화하면 {"code": "...", ...세부 정보} 형태라 프론트엔드·스크립트가 code 로 경우를 나눠
+// 처리하고 문구를 현지화할 수 있다. Display 는 그대로 사용자에게 보여줄 수 있는 한국어 문장.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// MIDI 파일을 악보로 변환할 수 없는 이유 ([`extract_midi_notes`](crate::extract_midi_notes), [`convert`](crate::convert))
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "code", rename_all = "snake_case")]
+pub enum ConversionError {
+    /// 표준 MIDI 파일로 읽을 수 없음. offset 은 읽기에 실패한 바이트 위치(0부터)
+    InvalidMidi { offset: usize, reason: String },
+    /// 시간 단위를 해석할 수 없음 (박당 틱 0, 서브프레임 0 인 SMPTE 등)
+    UnsupportedTiming,
+    /// 연주할 음이 하나도 없음
+    NoNotes,
+    /// 드럼 채널(10번) 음만 있어 선율 악기로 옮길 음이 없음
+    DrumOnly { drum_notes: usize },
+}
+
+impl ConversionError {
+    /// 직렬화했을 때의 code 값과 같은 기계용 식별자
+    pub fn code(&self) -> &'static str {
+        match self {
+            ConversionError::InvalidMidi { .. } => "invalid_midi",
+            ConversionError::UnsupportedTiming => "unsupported_timing",
+            ConversionError::NoNotes => "no_notes",
+            ConversionError::DrumOnly { .. } => "drum_only",
+        }
+    }
+}
+
+impl fmt::Display for ConversionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConversionError::InvalidMidi { offset, reason } => {
+                write!(f, "MIDI 파싱 오류 ({}번째 바이트): {}", offset, reason)
+            }
+            ConversionError::UnsupportedTiming => write!(f, "MIDI 시간 단위를 해석할 수 없음"),
+            ConversionError::NoNotes => write!(f, "연주할 음이 없는 곡입니다"),
+            ConversionError::DrumOnly { drum_notes } => {
+                write!(f, "드럼 음({}개)만 있어 변환할 음이 없습니다", drum_notes)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConversionError {}
+
+/// MML 문자열을 해석하지 못한 위치와 이유 ([`parse_mml`](crate::parse_mml)).
+/// 직렬화하면 code 는 "invalid_mml".
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "code", rename = "invalid_mml")]
+pub struct MmlParseError {
+    /// 1부터 센 글자(바이트) 번호
+    pub position: usize,
+    pub message: String,
+}
+
+impl fmt::Display for MmlParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "MML 해석 오류 ({}번째 글자): {}", self.position, self.message)
+    }
+}
+
+impl std::error::Error for MmlParseError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // code 는 직렬화 결과와 같아야 프론트엔드가 한 가지 기준으로 분기할 수 있다.
+    #[test]
+    fn serialized_code_matches_code() {
+        let errors = [
+            ConversionError::InvalidMidi {
+                offset: 14,
+                reason: "bad".to_string(),
+            },
+            ConversionError::UnsupportedTiming,
+            ConversionError::NoNotes,
+            ConversionError::DrumOnly { drum_notes: 3 },
+        ];
+        for e in errors {
+            let json = serde_json::to_value(&e).unwrap();
+            assert_eq!(json["code"], e.code(), "{json}");
+        }
+        let json = serde_json::to_value(ConversionError::InvalidMidi {
+            offset: 14,
+            reason: "bad".to_string(),
+        })
+        .unwrap();
+        assert_eq!(json["offset"], 14);
+    }
+
+    #[test]
+    fn mml_error_serializes_with_code() {
+        let e = MmlParseError {
+            position: 8,
+            message: "알 수 없는 문자 'X'".to_string(),
+        };
+        let json = serde_json::to_value(&e).unwrap();
+        assert_eq!(json["code"], "invalid_mml");
+        assert_eq!(json["position"], 8);
+    }
+}
@@ -0,0 +1,162 @@
+// 조성: MIDI 조표(FF 59)를 읽고, 조표가 없으면 음높이 분포(pitch-class profile)로 어림한다.
+// 플랫 조(F, B♭, E♭ …) 곡의 임시표를 샵(+) 대신 플랫(-)으로 적는 데 쓴다.
+
+use serde::{Deserialize, Serialize};
+
+use crate::converter::Note;
+
+// Krumhansl-Kessler 조성 프로필 (으뜸음부터 반음씩, 장조·단조)
+const MAJOR_PROFILE: [f64; 12] = [6.35, 2.23, 3.48, 2.33, 4.38, 4.09, 2.52, 5.19, 2.39, 3.66, 2.29, 2.88];
+const MINOR_PROFILE: [f64; 12] = [6.33, 2.68, 3.52, 5.38, 2.60, 3.53, 2.54, 4.75, 3.98, 2.69, 3.34, 3.17];
+
+// 조표 개수(-7 ~ 7)마다 으뜸음 이름
+const MAJOR_TONICS: [&str; 15] = [
+    "C♭", "G♭", "D♭", "A♭", "E♭", "B♭", "F", "C", "G", "D", "A", "E", "B", "F♯", "C♯",
+];
+const MINOR_TONICS: [&str; 15] = [
+    "A♭", "E♭", "B♭", "F", "C", "G", "D", "A", "E", "B", "F♯", "C♯", "G♯", "D♯", "A♯",
+];
+
+/// 조성. fifths 는 조표의 샵(양수)·플랫(음수) 개수 (C장조·A단조 = 0, G장조 = 1, F장조 = -1)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct Key {
+    pub fifths: i8,
+    pub minor: bool,
+    #[serde(default)]
+    pub estimated: bool, // 조표가 없어 음 분포로 어림한 조
+}
+
+impl Key {
+    /// MIDI 조표 메타 값 그대로 (범위 밖 개수는 ±7 로)
+    pub fn from_signature(fifths: i8, minor: bool) -> Self {
+        Key {
+            fifths: fifths.clamp(-7, 7),
+            minor,
+            estimated: false,
+        }
+    }
+
+    // 으뜸음(음이름 번호 0~11)의 조. 딴이름한소리 조는 조표가 적은 쪽 (F♯ 과 G♭ 은 F♯)
+    fn from_tonic(tonic: u8, minor: bool, estimated: bool) -> Self {
+        let major_tonic = (if minor { tonic + 3 } else { tonic }) as i32;
+        let fifths = (major_tonic * 7).rem_euclid(12);
+        Key {
+            fifths: (if fifths > 6 { fifths - 12 } else { fifths }) as i8,
+            minor,
+            estimated,
+        }
+    }
+
+    /// 음높이 분포(길이 가중, 드럼 음 제외)와 24개 조 프로필의 상관이 가장 큰 조. 같으면 조표가 적은 쪽.
+    pub fn estimate(notes: &[Note]) -> Self {
+        let mut histogram = [0.0; 12];
+        for n in notes.iter().filter(|n| !n.percussion) {
+            histogram[(n.note % 12) as usize] += n.duration as f64;
+        }
+        let mut best: Option<(f64, Key)> = None;
+        for minor in [false, true] {
+            let profile = if minor { &MINOR_PROFILE } else { &MAJOR_PROFILE };
+            for tonic in 0..12u8 {
+                let rotated: Vec<f64> = (0..12).map(|i| profile[(i + 12 - tonic as usize) % 12]).collect();
+                let score = correlation(&histogram, &rotated);
+                let key = Key::from_tonic(tonic, minor, true);
+                let better = best.is_none_or(|(s, k)| {
+                    score > s + 1e-9 || (score > s - 1e-9 && key.fifths.abs() < k.fifths.abs())
+                });
+                if better {
+                    best = Some((score, key));
+                }
+            }
+        }
+        best.map_or_else(Key::default, |(_, key)| key)
+    }
+
+    /// 으뜸음 (음이름 번호 0~11, C = 0)
+    pub fn tonic(&self) -> u8 {
+        let major_tonic = (self.fifths as i32 * 7).rem_euclid(12);
+        (major_tonic + if self.minor { 9 } else { 0 }) as u8 % 12
+    }
+
+    /// 임시표를 플랫(-)으로 적는 조인지
+    pub fn uses_flats(&self) -> bool {
+        self.fifths < 0
+    }
+
+    /// 반음 수만큼 옮긴 조
+    pub fn transposed(&self, semitones: i32) -> Self {
+        if semitones == 0 {
+            return *self;
+        }
+        let tonic = (self.tonic() as i32 + semitones).rem_euclid(12) as u8;
+        Key::from_tonic(tonic, self.minor, self.estimated)
+    }
+
+    /// 사람이 읽는 이름 (예: "E♭ 장조", "F♯ 단조")
+    pub fn name(&self) -> String {
+        let i = (self.fifths.clamp(-7, 7) + 7) as usize;
+        match self.minor {
+            true => format!("{} 단조", MINOR_TONICS[i]),
+            false => format!("{} 장조", MAJOR_TONICS[i]),
+        }
+    }
+}
+
+// 피어슨 상관계수. 음이 하나도 없으면 0.
+fn correlation(a: &[f64], b: &[f64]) -> f64 {
+    let mean = |v: &[f64]| v.iter().sum::<f64>() / v.len() as f64;
+    let (ma, mb) = (mean(a), mean(b));
+    let (mut cov, mut va, mut vb) = (0.0, 0.0, 0.0);
+    for (x, y) in a.iter().zip(b) {
+        cov += (x - ma) * (y - mb);
+        va += (x - ma) * (x - ma);
+        vb += (y - mb) * (y - mb);
+    }
+    if va == 0.0 || vb == 0.0 {
+        return 0.0;
+    }
+    cov / (va * vb).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn note(num: u8, duration: u32) -> Note {
+        Note {
+            note: num,
+            start: 0,
+            end: duration,
+            duration,
+            velocity: 100,
+            volume: 127,
+            program: 0,
+            track: 0,
+            track_name: None,
+            channel: 0,
+            percussion: false,
+        }
+    }
+
+    // 조표 개수로 으뜸음·이름을 정하고, 옮기면 조표가 적은 쪽 이름이 된다.
+    #[test]
+    fn signature_names_and_transposition() {
+        let e_flat = Key::from_signature(-3, false);
+        assert_eq!(e_flat.tonic(), 3);
+        assert_eq!(e_flat.name(), "E♭ 장조");
+        assert!(e_flat.uses_flats());
+        assert_eq!(Key::from_signature(-1, true).name(), "D 단조");
+        assert_eq!(e_flat.transposed(2), Key::from_signature(-1, false), "E♭ 장조 + 2 = F 장조");
+        assert_eq!(e_flat.transposed(-2).name(), "D♭ 장조");
+        assert_eq!(Key::from_signature(5, false).transposed(1).name(), "C 장조");
+    }
+
+    // B♭ 장조 음계를 으뜸화음 위주로 연주하면 B♭ 장조로 어림한다.
+    #[test]
+    fn estimates_flat_key_from_scale() {
+        let scale = [70, 72, 74, 75, 77, 79, 81];
+        let mut notes: Vec<Note> = scale.iter().map(|&p| note(p, 384)).collect();
+        notes.extend([70, 74, 77].iter().map(|&p| note(p, 1536)));
+        let key = Key::estimate(&notes);
+        assert_eq!((key.fifths, key.minor, key.estimated), (-2, false, true));
+    }
+}
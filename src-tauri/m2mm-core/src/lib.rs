@@ -0,0 +1,43 @@
+//! 딸깍악보 변환 엔진: MIDI 파일을 마비노기 모바일 MML 악보로 바꾼다.
+//!
+//! Tauri 없이 쓸 수 있어 데스크톱 앱과 명령줄 도구(`m2mm`), 다른 도구가 함께 쓴다.
+//!
+//! - 한 번에 변환: [`convert`] (MIDI 바이트 + [`ConversionOptions`] → 칸·보이스별 MML)
+//! - 단계별로: [`extract_midi_notes`] → [`allocate_voices_capped`] /
+//!   [`allocate_voices_by_instrument`] → [`generate_mml_final`]
+//! - 검증용 역변환: [`parse_mml`] → [`render_midi`], 미리 듣기: [`render_wav`]
+
+pub mod bar_map;
+pub mod conversion;
+pub mod converter;
+pub mod drum_parts;
+pub mod error;
+pub mod key;
+pub mod mml_optimizer;
+pub mod mml_parser;
+pub mod percussion;
+pub mod preview;
+pub mod simplify;
+pub mod utils;
+
+pub use bar_map::{BarMap, TimeSignature};
+pub use conversion::{
+    convert, list_tracks, ConversionOptions, ConversionResult, SongPosition, TrackInfo, Transpose,
+    VoiceFidelity, VoiceOctaveRange, VoiceResult, DEFAULT_CHAR_LIMIT,
+};
+pub use converter::{
+    allocate_voices_by_instrument, allocate_voices_capped, extract_midi_notes, fold_octaves,
+    generate_mml_final, generate_mml_with_offsets, max_polyphony, mml_octave, split_bass_line,
+    split_melody_line, transpose_notes, ExtractOptions, ExtractedMidi, MmlOptions, Note,
+    OctaveRange, PitchBendMode, SourceFilter, TempoChange, TPB,
+};
+pub use drum_parts::DrumParts;
+pub use error::{ConversionError, MmlParseError};
+pub use key::Key;
+pub use mml_optimizer::optimize_mml;
+pub use mml_parser::{parse_mml, render_midi, ParsedVoice};
+pub use percussion::{rhythm_voice, DrumRole, PercussionPitches};
+pub use preview::{encode_wav, render_samples, render_wav, PreviewVoice};
+pub use simplify::{
+    simplify_voice, Simplification, SimplifyLevel, BALANCE_STEPS, SIMPLIFY_LADDER,
+};
@@ -0,0 +1,685 @@
+// MML 최적화 패스: generate_mml_final 이 적은 MML 을 토큰으로 다시 읽어, 같은 음높이·타이밍을
+// 더 적은 글자로 다시 적는다. 악보 한 칸 글자 수 한도(char_limit)에 곡을 더 많이 담기 위함.
+//
+// - 옥타브: O{n} / 반복 > < / B+·C- 바운스 중 보이스 전체로 가장 짧은 조합 (DP)
+// - 쉼표: 이어진 쉼표는 합쳐 가장 짧은 길이 조합으로
+// - 타이: 점음표 등 더 짧은 표기로. 조각 수는 늘리지 않는다 (고음 타이 씹힘 방지)
+// - 기본 길이 L: 헤더 L 과, 같은 길이가 이어지는 구간 앞의 L 변경을 함께 고른다 (DP)
+// 다시 읽은 결과가 원래와 다르거나 더 길어지면 원래 MML 을 그대로 돌려준다.
+
+use std::collections::HashMap;
+
+use crate::converter::{get_exact_lengths, MML_OCTAVE_MAX, MML_OCTAVE_MIN, TPB};
+use crate::mml_parser::parse_mml;
+
+// 헤더에 O·L 이 없을 때의 MML 기본값
+const DEFAULT_OCTAVE: i32 = 4;
+const DEFAULT_LENGTH: u32 = TPB;
+
+// 쉼표 길이 조합 DP 의 단위 (모든 MML 길이가 4틱의 배수)와 최대 칸 수
+const REST_UNIT: u32 = 4;
+const REST_DP_MAX_UNITS: u32 = 1 << 20;
+
+const NOTE_NAMES: [&str; 12] = [
+    "C", "C+", "D", "D+", "E", "F", "F+", "G", "G+", "A", "A+", "B",
+];
+const FLAT_NOTE_NAMES: [&str; 12] = [
+    "C", "D-", "D", "E-", "E", "F", "G-", "G", "A-", "A", "B-", "B",
+];
+
+// 본문 토큰
+#[derive(Debug)]
+enum Token {
+    Command(String), // T·V 명령 (그대로 옮김)
+    Note {
+        pitch: i32,
+        flat: bool,       // 플랫(-)으로 적은 음 (C- 바운스 제외). 다시 적을 때도 플랫으로
+        pieces: Vec<u32>, // 타이(&)로 이은 조각 길이 (틱)
+        tie_after: bool,  // 다음 음과 타이로 이어짐 (사이에 T·V 가 끼어 있음)
+        continues: bool,  // 앞 음에서 타이로 이어진 음 (옥타브·표기를 바꾸지 않는다)
+    },
+    Rest(Vec<u32>),
+    NoteEnd, // 원래 음 하나가 끝난 자리 (음별 누적 글자 수를 다시 재는 표시)
+}
+
+// 헤더(첫 음·쉼표 전의 명령)와 본문
+#[derive(Debug)]
+struct Parsed {
+    header: Vec<String>,         // T·V 명령
+    start_octave: Option<i32>,   // 헤더 O (없으면 기본 O4 에서 시작하고 O 를 적지 않음)
+    default_length: Option<u32>, // 헤더 L
+    body: Vec<Token>,
+}
+
+// MML 을 토큰으로 읽는다. generate_mml_final 이 내보내지 않는 문법이면 None.
+// note_ends 의 위치마다 본문에 NoteEnd 표시를 끼운다.
+fn tokenize(mml: &str, lengths: &HashMap<u32, &str>, note_ends: &[usize]) -> Option<Parsed> {
+    let bytes = mml.as_bytes();
+    let mut pos = 0;
+    let number = |pos: &mut usize| {
+        let start = *pos;
+        while bytes.get(*pos).is_some_and(|b| b.is_ascii_digit()) {
+            *pos += 1;
+        }
+        mml[start..*pos].parse::<u32>().ok()
+    };
+    let length = |pos: &mut usize, default: u32| -> Option<u32> {
+        let base = match number(pos) {
+            Some(0) => return None,
+            Some(n) => (TPB * 4 + n / 2) / n,
+            None => default,
+        };
+        let mut ticks = base;
+        let mut add = base;
+        while bytes.get(*pos) == Some(&b'.') {
+            *pos += 1;
+            add /= 2;
+            ticks += add;
+        }
+        lengths.contains_key(&ticks).then_some(ticks)
+    };
+
+    let mut parsed = Parsed {
+        header: Vec::new(),
+        start_octave: None,
+        default_length: None,
+        body: Vec::new(),
+    };
+    let mut octave = DEFAULT_OCTAVE;
+    let mut default = DEFAULT_LENGTH;
+    let mut tie_pending = false;
+    let mut in_header = true;
+    let mut ends = note_ends.iter().peekable();
+
+    loop {
+        while ends.next_if(|&&end| end <= pos).is_some() {
+            parsed.body.push(Token::NoteEnd);
+        }
+        let Some(&b) = bytes.get(pos) else { break };
+        pos += 1;
+        match b {
+            b'T' | b'V' => {
+                let command = format!("{}{}", b as char, number(&mut pos)?);
+                match in_header {
+                    true => parsed.header.push(command),
+                    false => parsed.body.push(Token::Command(command)),
+                }
+            }
+            b'O' => {
+                octave = number(&mut pos)? as i32;
+                if in_header {
+                    parsed.start_octave = Some(octave);
+                }
+            }
+            b'L' => {
+                default = length(&mut pos, default)?;
+                if in_header {
+                    parsed.default_length = Some(default);
+                }
+            }
+            b'>' => octave += 1,
+            b'<' => octave -= 1,
+            b'&' => tie_pending = true,
+            b'R' => {
+                in_header = false;
+                tie_pending = false;
+                let ticks = length(&mut pos, default)?;
+                match parsed.body.last_mut() {
+                    Some(Token::Rest(pieces)) => pieces.push(ticks),
+                    _ => parsed.body.push(Token::Rest(vec![ticks])),
+                }
+            }
+            b'A'..=b'G' => {
+                in_header = false;
+                let class = match b {
+                    b'C' => 0,
+                    b'D' => 2,
+                    b'E' => 4,
+                    b'F' => 5,
+                    b'G' => 7,
+                    b'A' => 9,
+                    _ => 11,
+                };
+                let accidental = match bytes.get(pos) {
+                    Some(b'+') | Some(b'#') => 1,
+                    Some(b'-') => -1,
+                    _ => 0,
+                };
+                pos += (accidental != 0) as usize;
+                let pitch = (octave + 1) * 12 + class + accidental;
+                let ticks = length(&mut pos, default)?;
+
+                // 바로 앞 음과 타이로 붙어 있으면 조각을 더하고, T·V 를 사이에 두고 이어지면 표시만
+                let tied = std::mem::take(&mut tie_pending);
+                match parsed.body.last_mut() {
+                    Some(Token::Note {
+                        pitch: p, pieces, ..
+                    }) if tied && *p == pitch => pieces.push(ticks),
+                    _ => {
+                        let continues = tied
+                            && parsed.body.iter().rev().find_map(|t| match t {
+                                Token::Note { pitch: p, .. } => Some(*p == pitch),
+                                Token::Rest(_) | Token::NoteEnd => Some(false),
+                                Token::Command(_) => None,
+                            }) == Some(true);
+                        if continues {
+                            for token in parsed.body.iter_mut().rev() {
+                                if let Token::Note { tie_after, .. } = token {
+                                    *tie_after = true;
+                                    break;
+                                }
+                            }
+                        }
+                        parsed.body.push(Token::Note {
+                            pitch,
+                            flat: accidental < 0 && b != b'C',
+                            pieces: vec![ticks],
+                            tie_after: false,
+                            continues,
+                        });
+                    }
+                }
+            }
+            _ => return None,
+        }
+    }
+    Some(parsed)
+}
+
+// 길이 조각 하나를 적는 데 드는 글자 수 (기본 길이와 같으면 0)
+fn length_cost(ticks: u32, default: u32, lengths: &HashMap<u32, &str>) -> usize {
+    if ticks == default {
+        0
+    } else {
+        lengths[&ticks].len()
+    }
+}
+
+// O{n} 명령 글자 수 (옥타브는 1~8 한 자리)
+fn octave_command_len(octave: i32) -> usize {
+    1 + octave.unsigned_abs().checked_ilog10().unwrap_or(0) as usize + 1
+}
+
+// 옥타브 a → b 이동 글자 수: > < 반복과 O{n} 중 짧은 쪽
+fn octave_move_cost(a: i32, b: i32) -> usize {
+    match (a - b).unsigned_abs() as usize {
+        0 => 0,
+        d => d.min(octave_command_len(b)),
+    }
+}
+
+fn octave_move_text(a: i32, b: i32) -> String {
+    let d = (b - a).unsigned_abs() as usize;
+    if d == 0 {
+        String::new()
+    } else if d <= octave_command_len(b) {
+        (if b > a { ">" } else { "<" }).repeat(d)
+    } else {
+        format!("O{}", b)
+    }
+}
+
+// 타이로 이은 음의 길이 표기 후보: 원래 조각, 한 조각(점음표 포함), 두 조각. 조각 수는 늘리지 않는다.
+fn note_spellings(pieces: &[u32], lengths: &HashMap<u32, &str>) -> Vec<Vec<u32>> {
+    let total: u32 = pieces.iter().sum();
+    let mut spellings = vec![pieces.to_vec()];
+    if pieces.len() > 1 && lengths.contains_key(&total) {
+        spellings.push(vec![total]);
+    }
+    if pieces.len() > 2 {
+        for &a in lengths.keys() {
+            if a < total && a >= total - a && lengths.contains_key(&(total - a)) {
+                spellings.push(vec![a, total - a]);
+            }
+        }
+    }
+    spellings
+}
+
+// 쉼표 조합 DP 에 쓰는 길이들 (작은 것부터)
+fn rest_coins(lengths: &HashMap<u32, &str>) -> Vec<u32> {
+    let mut coins: Vec<u32> = lengths
+        .keys()
+        .copied()
+        .filter(|t| t.is_multiple_of(REST_UNIT))
+        .collect();
+    coins.sort_unstable();
+    coins
+}
+
+// 쉼표 total 틱을 가장 적은 글자로 적는 길이 조합 (기본 길이 default 기준)
+fn rest_spelling(
+    pieces: &[u32],
+    default: u32,
+    coins: &[u32],
+    lengths: &HashMap<u32, &str>,
+) -> Vec<u32> {
+    let total: u32 = pieces.iter().sum();
+    if !total.is_multiple_of(REST_UNIT) || total / REST_UNIT > REST_DP_MAX_UNITS {
+        return pieces.to_vec();
+    }
+    let units = (total / REST_UNIT) as usize;
+    // best[u] = (u 칸을 채우는 최소 글자 수, 마지막 조각)
+    let mut best: Vec<Option<(usize, u32)>> = vec![None; units + 1];
+    best[0] = Some((0, 0));
+    for u in 1..=units {
+        for &coin in coins {
+            let c = (coin / REST_UNIT) as usize;
+            if c > u {
+                break;
+            }
+            if let Some((cost, _)) = best[u - c] {
+                let cost = cost + 1 + length_cost(coin, default, lengths);
+                if best[u].is_none_or(|(b, _)| cost < b) {
+                    best[u] = Some((cost, coin));
+                }
+            }
+        }
+    }
+    let mut spelled = Vec::new();
+    let mut u = units;
+    while u > 0 {
+        let Some((_, coin)) = best[u] else {
+            return pieces.to_vec();
+        };
+        spelled.push(coin);
+        u -= (coin / REST_UNIT) as usize;
+    }
+    spelled.sort_unstable_by(|a, b| b.cmp(a)); // 긴 조각부터
+    spelled
+}
+
+// 음 하나를 적는 방법: (옥타브, 음이름). 도는 아래 옥타브 B+, 시는 위 옥타브 C- 로도 적을 수 있다.
+// flat 이면 임시표를 원래대로 플랫으로 적는다.
+fn note_spelling_options(pitch: i32, flat: bool) -> Vec<(i32, &'static str)> {
+    let octave = pitch.div_euclid(12) - 1;
+    let class = pitch.rem_euclid(12) as usize;
+    let names = if flat { &FLAT_NOTE_NAMES } else { &NOTE_NAMES };
+    let mut options = vec![(octave, names[class])];
+    match class {
+        0 => options.push((octave - 1, "B+")),
+        11 => options.push((octave + 1, "C-")),
+        _ => {}
+    }
+    options.retain(|&(o, _)| (MML_OCTAVE_MIN..=MML_OCTAVE_MAX).contains(&o));
+    options
+}
+
+// 옥타브·표기를 정한 출력 조각
+enum Atom {
+    Text(String),
+    NoteEnd,
+    Piece {
+        prefix: &'static str, // 음이름 또는 "R"
+        ticks: u32,
+        tied: bool, // 앞 조각과 타이로 이어짐 (바로 앞에 L 을 끼우지 않는다)
+    },
+}
+
+// 옥타브 DP: 음마다 (옥타브, 음이름, 길이 표기)를 골라 옥타브 이동과 음 표기 글자 수 합을 최소로.
+fn place_octaves(parsed: &Parsed, default: u32, lengths: &HashMap<u32, &str>) -> (i32, Vec<Atom>) {
+    const STATES: usize = (MML_OCTAVE_MAX - MML_OCTAVE_MIN + 1) as usize;
+    let index = |octave: i32| (octave - MML_OCTAVE_MIN) as usize;
+    let octave_of = |i: usize| i as i32 + MML_OCTAVE_MIN;
+
+    // 비용은 (글자 수, B+·C- 로 적은 음 수): 글자 수가 같으면 원래 음이름을 쓴 쪽을 고른다.
+    // 시작 상태: 헤더 O 가 있으면 어느 옥타브로든 시작(O{n} 글자), 없으면 O4 고정
+    let mut cost: [Option<(usize, usize)>; STATES] = [None; STATES];
+    match parsed.start_octave {
+        Some(_) => (0..STATES).for_each(|i| cost[i] = Some((octave_command_len(octave_of(i)), 0))),
+        None => cost[index(DEFAULT_OCTAVE)] = Some((0, 0)),
+    }
+
+    // 음마다 적는 방법 후보 (옥타브, 음이름, 길이 조각, 글자 수)와
+    // [도착 옥타브] → (이전 옥타브, 고른 후보 번호)
+    type Spelling = (i32, &'static str, Vec<u32>, usize);
+    let mut note_options: Vec<Vec<Spelling>> = Vec::new();
+    let mut choices: Vec<[Option<(usize, usize)>; STATES]> = Vec::new();
+    for token in &parsed.body {
+        let Token::Note {
+            pitch,
+            flat,
+            pieces,
+            continues,
+            ..
+        } = token
+        else {
+            continue;
+        };
+        let spellings: Vec<Vec<u32>> = match continues {
+            true => vec![pieces.clone()],
+            false => note_spellings(pieces, lengths),
+        };
+        let options: Vec<Spelling> = note_spelling_options(*pitch, *flat)
+            .into_iter()
+            .map(|(octave, name)| {
+                let (body, spelled) = spellings
+                    .iter()
+                    .map(|s| {
+                        let chars = s
+                            .iter()
+                            .map(|&t| name.len() + length_cost(t, default, lengths));
+                        (chars.sum::<usize>() + s.len() - 1, s)
+                    })
+                    .min_by_key(|(c, _)| *c)
+                    .expect("원래 표기는 항상 후보");
+                (octave, name, spelled.clone(), body)
+            })
+            .collect();
+
+        let mut next: [Option<(usize, usize)>; STATES] = [None; STATES];
+        let mut choice: [Option<(usize, usize)>; STATES] = [None; STATES];
+        for (i, &(octave, _, _, body)) in options.iter().enumerate() {
+            let b = index(octave);
+            let bounce = (i > 0) as usize;
+            for (a, c) in cost.iter().enumerate() {
+                let Some((chars, bounces)) = *c else { continue };
+                if *continues && a != b {
+                    continue; // 타이로 이어지는 음은 옥타브를 옮기지 않는다
+                }
+                let total = (
+                    chars + octave_move_cost(octave_of(a), octave) + body,
+                    bounces + bounce,
+                );
+                if next[b].is_none_or(|n| total < n) {
+                    next[b] = Some(total);
+                    choice[b] = Some((a, i));
+                }
+            }
+        }
+        cost = next;
+        choices.push(choice);
+        note_options.push(options);
+    }
+
+    // 역추적
+    let mut state = (0..STATES)
+        .filter(|&i| cost[i].is_some())
+        .min_by_key(|&i| cost[i])
+        .unwrap_or(0);
+    let mut picked: Vec<(i32, &'static str, Vec<u32>)> = Vec::with_capacity(choices.len());
+    for (choice, options) in choices.iter().zip(note_options).rev() {
+        let (prev, i) = choice[state].unwrap_or((state, 0));
+        let (_, name, spelled, _) = options.into_iter().nth(i).expect("고른 후보");
+        picked.push((octave_of(state), name, spelled));
+        state = prev;
+    }
+    picked.reverse();
+    let start = match parsed.start_octave {
+        Some(_) => octave_of(state),
+        None => DEFAULT_OCTAVE,
+    };
+
+    let coins = rest_coins(lengths);
+    let mut atoms = Vec::new();
+    let mut picked = picked.into_iter();
+    let mut octave = start;
+    for token in &parsed.body {
+        match token {
+            Token::Command(command) => atoms.push(Atom::Text(command.clone())),
+            Token::NoteEnd => atoms.push(Atom::NoteEnd),
+            Token::Rest(pieces) => {
+                for ticks in rest_spelling(pieces, default, &coins, lengths) {
+                    atoms.push(Atom::Piece {
+                        prefix: "R",
+                        ticks,
+                        tied: false,
+                    });
+                }
+            }
+            Token::Note {
+                tie_after,
+                continues,
+                ..
+            } => {
+                let (target, name, spelled) = picked.next().expect("음마다 고른 표기");
+                atoms.push(Atom::Text(octave_move_text(octave, target)));
+                octave = target;
+                if *continues {
+                    atoms.push(Atom::Text("&".to_string())); // T·V 뒤에서 앞 음과 다시 잇는다
+                }
+                for (i, ticks) in spelled.into_iter().enumerate() {
+                    if i > 0 {
+                        atoms.push(Atom::Text("&".to_string()));
+                    }
+                    atoms.push(Atom::Piece {
+                        prefix: name,
+                        ticks,
+                        tied: i > 0 || *continues,
+                    });
+                }
+                if *tie_after {
+                    atoms.push(Atom::Text("&".to_string()));
+                }
+            }
+        }
+    }
+    (start, atoms)
+}
+
+// 기본 길이 DP: 조각마다 그 시점의 L 을 골라, L 변경 글자 + 길이 글자 합을 최소로.
+// 반환: 조각 순서대로 그 조각 앞에서 바꿀 L (바꾸지 않으면 None)
+// 헤더 L 이 있으면 헤더 L 도 DP 로 고른다 (어느 길이든 같은 L{n} 글자). 반환: (시작 L, 변경 목록)
+fn place_lengths(
+    atoms: &[Atom],
+    header: Option<u32>,
+    lengths: &HashMap<u32, &str>,
+) -> (u32, Vec<Option<u32>>) {
+    let pieces: Vec<(u32, bool)> = atoms
+        .iter()
+        .filter_map(|a| match a {
+            Atom::Piece { ticks, tied, .. } => Some((*ticks, *tied)),
+            Atom::Text(_) | Atom::NoteEnd => None,
+        })
+        .collect();
+    let mut states: Vec<u32> = pieces.iter().map(|&(t, _)| t).collect();
+    states.push(header.unwrap_or(DEFAULT_LENGTH));
+    states.sort_unstable();
+    states.dedup();
+    let switch_cost: Vec<usize> = states.iter().map(|l| 1 + lengths[l].len()).collect();
+
+    let mut cost: Vec<Option<usize>> = states
+        .iter()
+        .map(|&l| match header {
+            Some(_) => Some(1 + lengths[&l].len()),
+            None => (l == DEFAULT_LENGTH).then_some(0),
+        })
+        .collect();
+    let mut back: Vec<Vec<usize>> = Vec::with_capacity(pieces.len()); // [조각][상태] → 이전 상태
+    for &(ticks, tied) in &pieces {
+        let piece_len = lengths[&ticks].len();
+        // L 을 바꿔 들어오는 경우는 지금까지 가장 싼 상태에서 오는 것만 보면 된다
+        let cheapest = (0..states.len())
+            .filter_map(|a| cost[a].map(|c| (c, a)))
+            .min();
+        let mut next = vec![None; states.len()];
+        let mut from = vec![0; states.len()];
+        for (b, &l) in states.iter().enumerate() {
+            let here = if l == ticks { 0 } else { piece_len };
+            let stay = cost[b].map(|c| (c + here, b));
+            let switch = cheapest
+                .filter(|&(_, a)| a != b && !tied) // 타이 바로 뒤에서는 L 을 바꾸지 않는다
+                .map(|(c, a)| (c + switch_cost[b] + here, a));
+            if let Some((total, a)) = [stay, switch].into_iter().flatten().min_by_key(|&(c, _)| c) {
+                next[b] = Some(total);
+                from[b] = a;
+            }
+        }
+        cost = next;
+        back.push(from);
+    }
+
+    let mut state = (0..states.len())
+        .filter(|&i| cost[i].is_some())
+        .min_by_key(|&i| cost[i])
+        .unwrap_or(0);
+    let mut changes = vec![None; pieces.len()];
+    for (i, from) in back.iter().enumerate().rev() {
+        let prev = from[state];
+        if prev != state {
+            changes[i] = Some(states[state]);
+        }
+        state = prev;
+    }
+    (states[state], changes)
+}
+
+// 다시 적은 MML 과 NoteEnd 마다 그때까지 적은 글자 수
+fn render(parsed: &Parsed, lengths: &HashMap<u32, &str>) -> (String, Vec<usize>) {
+    let initial = parsed.default_length.unwrap_or(DEFAULT_LENGTH);
+    let (start_octave, atoms) = place_octaves(parsed, initial, lengths);
+    let (start_length, mut changes) = place_lengths(&atoms, parsed.default_length, lengths);
+
+    // 첫 조각 앞의 L 변경은 헤더 L 을 바꾸는 것으로 (앞에 오는 T·V·옥타브는 길이와 무관)
+    let mut default = start_length;
+    let mut header_length = parsed.default_length.map(|_| start_length);
+    if let Some(first) = changes.first_mut()
+        && let Some(l) = first.take()
+    {
+        default = l;
+        header_length = Some(l);
+    }
+
+    let mut out: String = parsed.header.concat();
+    if parsed.start_octave.is_some() {
+        out.push_str(&format!("O{}", start_octave));
+    }
+    if let Some(l) = header_length {
+        out.push_str(&format!("L{}", lengths[&l]));
+    }
+    let mut changes = changes.into_iter();
+    let mut note_ends = Vec::new();
+    for atom in atoms {
+        match atom {
+            Atom::Text(text) => out.push_str(&text),
+            Atom::NoteEnd => note_ends.push(out.len()),
+            Atom::Piece { prefix, ticks, .. } => {
+                if let Some(Some(l)) = changes.next() {
+                    out.push_str(&format!("L{}", lengths[&l]));
+                    default = l;
+                }
+                out.push_str(prefix);
+                if ticks != default {
+                    out.push_str(lengths[&ticks]);
+                }
+            }
+        }
+    }
+    (out, note_ends)
+}
+
+// 두 MML 이 같은 음(높이·시작·끝·셈여림)과 템포 변경으로 읽히는지
+fn same_performance(a: &str, b: &str) -> bool {
+    match (parse_mml(a), parse_mml(b)) {
+        (Ok(a), Ok(b)) => {
+            let notes = |v: &crate::mml_parser::ParsedVoice| -> Vec<(u8, u32, u32, u8)> {
+                v.notes
+                    .iter()
+                    .map(|n| (n.note, n.start, n.end, n.velocity))
+                    .collect()
+            };
+            let tempos = |v: &crate::mml_parser::ParsedVoice| -> Vec<(u32, u32)> {
+                v.tempo_changes.iter().map(|t| (t.tick, t.bpm)).collect()
+            };
+            notes(&a) == notes(&b) && tempos(&a) == tempos(&b)
+        }
+        _ => false,
+    }
+}
+
+/// MML 을 같은 음높이·타이밍으로 더 짧게 다시 적는다. 줄일 수 없거나 해석할 수 없으면 그대로.
+pub fn optimize_mml(mml: &str) -> String {
+    optimize_mml_with_offsets(mml, &[]).0
+}
+
+/// optimize_mml 과 같되, 원래 MML 의 음별 누적 글자 수(note_ends)를 다시 적은 MML 기준으로 옮겨 준다.
+/// 음 수는 그대로다 (줄이지 못하면 원래 MML 과 note_ends 그대로).
+pub fn optimize_mml_with_offsets(mml: &str, note_ends: &[usize]) -> (String, Vec<usize>) {
+    let unchanged = || (mml.to_string(), note_ends.to_vec());
+    let lengths = get_exact_lengths();
+    let Some(parsed) = tokenize(mml, &lengths, note_ends) else {
+        return unchanged();
+    };
+    let (optimized, ends) = render(&parsed, &lengths);
+    if optimized.len() < mml.len()
+        && ends.len() == note_ends.len()
+        && same_performance(mml, &optimized)
+    {
+        (optimized, ends)
+    } else {
+        unchanged()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 한 음만 옥타브를 넘었다 돌아오는 패턴은 B+ / C- 로
+    #[test]
+    fn octave_bounces_become_sharp_or_flat() {
+        assert_eq!(optimize_mml("C>C<C"), "CB+C");
+        assert_eq!(optimize_mml("C<B>C"), "CC-C");
+        assert_eq!(optimize_mml(">C16<"), ">C16"); // 끝의 < 만 빠지면 B+16 과 같은 길이: 원래 음이름 유지
+        assert_eq!(optimize_mml(">C<D"), "B+D");
+        assert_eq!(optimize_mml(">C>C"), ">C>C"); // 안 돌아오면 그대로
+        assert_eq!(optimize_mml(">C+<"), ">C+"); // 샵(C+)은 B+ 로 못 바꿈, 끝의 < 만 빠짐
+    }
+
+    // 플랫으로 적은 음은 다시 적어도 플랫 (C- 바운스는 플랫 표기가 아니라 시)
+    #[test]
+    fn flat_spelling_is_kept() {
+        assert_eq!(optimize_mml("E-B-E-"), "E-B-E-");
+        assert_eq!(optimize_mml("B->C<B-"), "B-B+B-");
+        assert_eq!(optimize_mml("C<B>C"), "CC-C");
+    }
+
+    // 옥타브를 여러 칸 건너면 > 반복과 O{n} 중 짧은 쪽
+    #[test]
+    fn octave_jumps_use_shorter_form() {
+        assert_eq!(optimize_mml("O2CO6C"), "O2CO6C");
+        assert_eq!(optimize_mml("O4CO5D"), "O4C>D");
+    }
+
+    // 이어진 쉼표는 합치고, 타이는 점음표로
+    #[test]
+    fn rests_merge_and_ties_become_dotted() {
+        assert_eq!(optimize_mml("T120O4L8CR16R16D"), "T120O4L8CRD");
+        assert_eq!(optimize_mml("T120O4L8C4&C8D"), "T120O4L8C4.D");
+        // 조각 수는 늘리지 않는다 (한 조각 음을 타이로 쪼개지 않음)
+        assert!(!optimize_mml("T120O4L4C2.").contains('&'));
+    }
+
+    // 헤더 L 도 가장 많이 아끼는 길이로 다시 고른다
+    #[test]
+    fn header_length_is_chosen_too() {
+        let mml = format!("T120O4L4{}", "D16".repeat(12));
+        assert_eq!(optimize_mml(&mml), format!("T120O4L16{}", "D".repeat(12)));
+    }
+
+    // 같은 길이가 길게 이어지는 구간에서는 중간에 L 을 바꾼다
+    #[test]
+    fn default_length_changes_mid_stream() {
+        let mml = format!("T120O4L4{}{}", "C".repeat(6), "D16".repeat(12));
+        let optimized = optimize_mml(&mml);
+        assert_eq!(
+            optimized,
+            format!("T120O4L4{}L16{}", "C".repeat(6), "D".repeat(12))
+        );
+    }
+
+    // 템포 변경을 사이에 둔 타이는 그대로 이어지고, 결과는 언제나 같은 연주로 읽힌다
+    #[test]
+    fn ties_across_tempo_changes_are_kept() {
+        let mml = "T120O5L8C4&T90C8D";
+        let optimized = optimize_mml(mml);
+        assert!(optimized.contains("&T90"), "{optimized}");
+        assert!(same_performance(mml, &optimized));
+    }
+
+    // 모르는 문법이면 손대지 않는다
+    #[test]
+    fn unknown_syntax_is_left_alone() {
+        assert_eq!(optimize_mml("T120O4L8CNX"), "T120O4L8CNX");
+    }
+}
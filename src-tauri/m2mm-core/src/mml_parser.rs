@@ -0,0 +1,410 @@
+use crate::converter::{Note, TempoChange, DRUM_CHANNEL, TPB};
+use crate::error::MmlParseError;
+
+// 마비노기 MML 을 다시 음표로 읽어 MIDI 로 되돌린다 (generate_mml_final 이 내보내는 문법).
+// 게임에 붙여넣을 MML 이 원본과 같은 타이밍인지 DAW 에서 비교하는 검증용.
+//
+// 지원: T V O L > < & R, 음이름 C~B + 샵(+,#)/플랫(-), 길이 숫자, 점음표(.)
+//       B+ / C- 옥타브 바운스는 샵·플랫 규칙 그대로 윗옥타브 도 / 아랫옥타브 시가 된다.
+
+// MML 기본값 (헤더가 없을 때)
+const DEFAULT_OCTAVE: i32 = 4;
+const DEFAULT_TEMPO: u32 = 120;
+const DEFAULT_VOLUME: u32 = 8;
+const MML_VOLUME_MAX: u32 = 15;
+
+// 드럼 채널을 뺀 멜로디 채널 수
+const MELODIC_CHANNELS: usize = 15;
+
+// 한 보이스(MML 한 줄)를 읽은 결과
+#[derive(Debug, Clone)]
+pub struct ParsedVoice {
+    pub notes: Vec<Note>,
+    pub tempo_changes: Vec<TempoChange>,
+    // 음마다 그 음(타이로 이은 조각까지)이 끝나는 바이트 위치. 앞에서 몇 음까지 몇 글자인지 셀 때 쓴다.
+    pub note_ends: Vec<usize>,
+}
+
+// 바이트 커서. 위치는 오류 메시지에 1부터 센 글자 번호로 쓴다.
+struct Cursor<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl Cursor<'_> {
+    fn peek(&self) -> Option<u8> {
+        self.bytes.get(self.pos).copied()
+    }
+
+    fn error(&self, message: &str) -> MmlParseError {
+        MmlParseError {
+            position: self.pos + 1,
+            message: message.to_string(),
+        }
+    }
+
+    // 숫자 읽기 (없으면 None)
+    fn number(&mut self) -> Option<u32> {
+        let start = self.pos;
+        while self.peek().is_some_and(|b| b.is_ascii_digit()) {
+            self.pos += 1;
+        }
+        std::str::from_utf8(&self.bytes[start..self.pos])
+            .ok()?
+            .parse()
+            .ok()
+    }
+
+    // 숫자가 꼭 와야 하는 명령(T/V/O)의 값
+    fn required_number(&mut self, command: char) -> Result<u32, MmlParseError> {
+        self.number()
+            .ok_or_else(|| self.error(&format!("{command} 뒤에 숫자가 없음")))
+    }
+
+    // 길이(숫자 + 점). 숫자가 없으면 기본 길이 L 을 쓴다. 반환: 틱
+    fn length(&mut self, default_ticks: u32) -> Result<u32, MmlParseError> {
+        let base = match self.number() {
+            Some(0) => return Err(self.error("길이 0은 쓸 수 없음")),
+            Some(n) => (TPB * 4 + n / 2) / n,
+            None => default_ticks,
+        };
+        // 점마다 직전에 더한 길이의 절반을 더한다 (4. = 4 + 8, 4.. = 4 + 8 + 16)
+        let mut ticks = base;
+        let mut add = base;
+        while self.peek() == Some(b'.') {
+            self.pos += 1;
+            add /= 2;
+            ticks += add;
+        }
+        Ok(ticks)
+    }
+}
+
+// MML 음량 V0~V15 → MIDI 벨로시티
+fn velocity_for(volume: u32) -> u8 {
+    (volume.min(MML_VOLUME_MAX) * 127 / MML_VOLUME_MAX) as u8
+}
+
+/// MML 한 줄을 음표·템포 변경으로 읽는다. 틱 단위는 변환기와 같은 TPB.
+/// 타이(&)로 이어진 같은 높이의 음은 한 음으로 합친다.
+pub fn parse_mml(mml: &str) -> Result<ParsedVoice, MmlParseError> {
+    let mut cur = Cursor {
+        bytes: mml.as_bytes(),
+        pos: 0,
+    };
+    let mut notes: Vec<Note> = Vec::new();
+    let mut note_ends: Vec<usize> = Vec::new();
+    let mut tempo_changes = vec![TempoChange {
+        tick: 0,
+        bpm: DEFAULT_TEMPO,
+    }];
+    let mut octave = DEFAULT_OCTAVE;
+    let mut default_ticks = TPB; // L4
+    let mut volume = DEFAULT_VOLUME;
+    let mut tick = 0u32;
+    let mut tie_pending = false;
+
+    while let Some(b) = cur.peek() {
+        let c = b.to_ascii_uppercase();
+        cur.pos += 1;
+        match c {
+            b' ' | b'\t' | b'\r' | b'\n' => {}
+            b'T' => {
+                let bpm = cur.required_number('T')?;
+                if bpm == 0 {
+                    return Err(cur.error("템포 0은 쓸 수 없음"));
+                }
+                // 같은 시점의 템포는 마지막 값만 남긴다
+                match tempo_changes.last_mut() {
+                    Some(last) if last.tick == tick => last.bpm = bpm,
+                    _ => tempo_changes.push(TempoChange { tick, bpm }),
+                }
+            }
+            b'V' => volume = cur.required_number('V')?,
+            b'O' => octave = cur.required_number('O')? as i32,
+            b'L' => default_ticks = cur.length(default_ticks)?,
+            b'>' => octave += 1,
+            b'<' => octave -= 1,
+            b'&' => tie_pending = true,
+            b'R' => {
+                tick += cur.length(default_ticks)?;
+                tie_pending = false;
+            }
+            b'A'..=b'G' => {
+                let pitch_class = match c {
+                    b'C' => 0,
+                    b'D' => 2,
+                    b'E' => 4,
+                    b'F' => 5,
+                    b'G' => 7,
+                    b'A' => 9,
+                    _ => 11, // B
+                };
+                let accidental = match cur.peek() {
+                    Some(b'+') | Some(b'#') => {
+                        cur.pos += 1;
+                        1
+                    }
+                    Some(b'-') => {
+                        cur.pos += 1;
+                        -1
+                    }
+                    _ => 0,
+                };
+                let pitch = (octave + 1) * 12 + pitch_class + accidental;
+                if !(0..=127).contains(&pitch) {
+                    return Err(cur.error("음높이가 MIDI 범위(0~127)를 벗어남"));
+                }
+                let pitch = pitch as u8;
+                let ticks = cur.length(default_ticks)?;
+
+                // 타이: 바로 앞 음과 높이가 같고 끊김 없이 이어지면 그 음을 늘린다
+                let tied = tie_pending
+                    && notes
+                        .last()
+                        .is_some_and(|last| last.note == pitch && last.end == tick);
+                if tied {
+                    if let Some(last) = notes.last_mut() {
+                        last.end += ticks;
+                        last.duration += ticks;
+                    }
+                    note_ends.pop();
+                } else {
+                    notes.push(Note {
+                        note: pitch,
+                        start: tick,
+                        end: tick + ticks,
+                        duration: ticks,
+                        velocity: velocity_for(volume),
+                        volume: 127,
+                        program: 0,
+                        track: 0,
+                        track_name: None,
+                        channel: 0,
+                        percussion: false,
+                    });
+                }
+                note_ends.push(cur.pos);
+                tick += ticks;
+                tie_pending = false;
+            }
+            _ => {
+                cur.pos -= 1;
+                let ch = mml[cur.pos..].chars().next().unwrap_or('?');
+                return Err(cur.error(&format!("알 수 없는 문자 '{ch}'")));
+            }
+        }
+    }
+
+    Ok(ParsedVoice {
+        notes,
+        tempo_changes,
+        note_ends,
+    })
+}
+
+/// 읽어 들인 보이스들을 표준 MIDI 파일(format 1) 바이트로 만든다.
+/// 0번 트랙은 템포 맵(첫 보이스의 T), 이후 보이스마다 트랙 하나·채널 하나(드럼 채널 10 제외).
+/// 보이스가 멜로디 채널 15개보다 많으면 채널을 처음부터 다시 돌려 쓴다 (트랙은 보이스마다 따로).
+pub fn render_midi(voices: &[ParsedVoice]) -> Vec<u8> {
+    use midly::{
+        Format, Header, MetaMessage, MidiMessage, Smf, Timing, TrackEvent, TrackEventKind,
+    };
+
+    // (절대 tick, 이벤트) → 델타 타임 트랙. 같은 tick 이면 넣은 순서 유지.
+    fn to_track(mut events: Vec<(u32, TrackEventKind<'static>)>) -> Vec<TrackEvent<'static>> {
+        events.sort_by_key(|&(tick, _)| tick);
+        let mut last = 0u32;
+        let mut track: Vec<TrackEvent> = events
+            .into_iter()
+            .map(|(tick, kind)| {
+                let delta = tick - last;
+                last = tick;
+                TrackEvent {
+                    delta: delta.into(),
+                    kind,
+                }
+            })
+            .collect();
+        track.push(TrackEvent {
+            delta: 0.into(),
+            kind: TrackEventKind::Meta(MetaMessage::EndOfTrack),
+        });
+        track
+    }
+
+    let mut smf = Smf::new(Header::new(
+        Format::Parallel,
+        Timing::Metrical((TPB as u16).into()),
+    ));
+
+    // 템포 맵
+    let tempo_events = voices
+        .first()
+        .map(|v| v.tempo_changes.as_slice())
+        .unwrap_or_default()
+        .iter()
+        .map(|t| {
+            let us = 60_000_000 / t.bpm.max(1);
+            (t.tick, TrackEventKind::Meta(MetaMessage::Tempo(us.into())))
+        })
+        .collect();
+    smf.tracks.push(to_track(tempo_events));
+
+    for (i, voice) in voices.iter().enumerate() {
+        let slot = (i % MELODIC_CHANNELS) as u8;
+        let channel = if slot >= DRUM_CHANNEL { slot + 1 } else { slot };
+        let mut events = Vec::new();
+        for n in voice.notes.iter().filter(|n| n.velocity > 0) {
+            let on = MidiMessage::NoteOn {
+                key: n.note.into(),
+                vel: n.velocity.into(),
+            };
+            let off = MidiMessage::NoteOff {
+                key: n.note.into(),
+                vel: 0.into(),
+            };
+            events.push((n.start, channel, on));
+            events.push((n.end, channel, off));
+        }
+        // 같은 tick 에선 NoteOff 먼저 (끝나는 음과 새 음이 붙어 있어도 끊기지 않게)
+        events.sort_by_key(|&(tick, _, msg)| (tick, matches!(msg, MidiMessage::NoteOn { .. })));
+        smf.tracks.push(to_track(
+            events
+                .into_iter()
+                .map(|(tick, ch, message)| {
+                    (
+                        tick,
+                        TrackEventKind::Midi {
+                            channel: ch.into(),
+                            message,
+                        },
+                    )
+                })
+                .collect(),
+        ));
+    }
+
+    let mut bytes = Vec::new();
+    smf.write_std(&mut bytes)
+        .expect("메모리 버퍼에 쓰기는 실패하지 않음");
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::converter::{generate_mml_final, MmlOptions};
+
+    fn note(num: u8, start: u32, duration: u32) -> Note {
+        Note {
+            note: num,
+            start,
+            end: start + duration,
+            duration,
+            velocity: 100,
+            volume: 127,
+            program: 0,
+            track: 0,
+            track_name: None,
+            channel: 0,
+            percussion: false,
+        }
+    }
+
+    fn spans(notes: &[Note]) -> Vec<(u8, u32, u32)> {
+        notes.iter().map(|n| (n.note, n.start, n.end)).collect()
+    }
+
+    // 생성한 MML 을 다시 읽으면 원래 음높이·타이밍이 그대로 나와야 한다.
+    // (옥타브 바운스 B+/C-, 점음표, 타이, 셋잇단, 쉼표 포함)
+    #[test]
+    fn round_trip_preserves_pitch_and_timing() {
+        let notes = vec![
+            note(60, 0, 384),     // C4
+            note(72, 384, 192),   // C5 → B+ 바운스
+            note(62, 576, 576),   // D4 점4분
+            note(59, 1152, 192),  // B3 → C- 바운스
+            note(60, 1344, 96),
+            note(64, 1536, 1000), // 타이로 나뉘는 길이 + 뒤 쉼표
+            note(67, 2688, 128),  // 셋잇단 8분
+            note(69, 2816, 128),
+        ];
+        let mml = generate_mml_final(&notes, 120, 4, &[], &MmlOptions::default());
+        assert!(mml.contains("B+") && mml.contains("C-"), "바운스가 안 생김: {mml}");
+
+        let parsed = parse_mml(&mml).unwrap();
+        assert_eq!(spans(&parsed.notes), spans(&notes), "MML: {mml}");
+        assert_eq!(parsed.tempo_changes[0].bpm, 120);
+    }
+
+    // 템포 변경 경계에서 타이로 나뉜 음은 한 음으로 다시 합쳐지고, 템포는 그 시점에 기록된다.
+    // 합친 음의 끝 위치는 마지막 조각 뒤다.
+    #[test]
+    fn tie_across_tempo_change_is_one_note() {
+        let parsed = parse_mml("T120O4L4C&T90C R").unwrap();
+        assert_eq!(spans(&parsed.notes), vec![(60, 0, 768)]);
+        assert_eq!(parsed.note_ends, vec![14]);
+        let tempos: Vec<(u32, u32)> = parsed.tempo_changes.iter().map(|t| (t.tick, t.bpm)).collect();
+        assert_eq!(tempos, vec![(0, 120), (384, 90)]);
+    }
+
+    // 잘못된 문자는 위치와 함께 오류로 알린다.
+    #[test]
+    fn unknown_command_reports_position() {
+        let err = parse_mml("T120O4CX").unwrap_err();
+        assert_eq!(err.position, 8, "{err}");
+        assert!(err.to_string().contains("8번째"), "{err}");
+    }
+
+    // MIDI 로 렌더링하면 템포 트랙 + 보이스별 트랙으로 다시 읽을 수 있어야 한다.
+    #[test]
+    fn render_midi_is_readable_smf() {
+        let voices = vec![
+            parse_mml("T150V15O4L8CDEF").unwrap(),
+            parse_mml("T150V10O3L4C&CG").unwrap(),
+        ];
+        let bytes = render_midi(&voices);
+        let smf = midly::Smf::parse(&bytes).unwrap();
+        assert_eq!(smf.tracks.len(), 3);
+
+        let note_ons = |track: &[midly::TrackEvent]| {
+            track
+                .iter()
+                .filter(|e| {
+                    matches!(
+                        e.kind,
+                        midly::TrackEventKind::Midi {
+                            message: midly::MidiMessage::NoteOn { .. },
+                            ..
+                        }
+                    )
+                })
+                .count()
+        };
+        assert_eq!(note_ons(&smf.tracks[1]), 4);
+        assert_eq!(note_ons(&smf.tracks[2]), 2, "타이로 이은 음은 한 번만 울려야 함");
+    }
+
+    // 보이스가 15개를 넘어도 드럼 채널(10)은 건너뛰고, 16번째 보이스부터 채널을 처음부터 다시 쓴다.
+    #[test]
+    fn render_midi_reuses_channels_without_drums() {
+        let voices = vec![parse_mml("T120O4L8C").unwrap(); 17];
+        let smf_bytes = render_midi(&voices);
+        let smf = midly::Smf::parse(&smf_bytes).unwrap();
+        let channels: Vec<u8> = smf.tracks[1..]
+            .iter()
+            .map(|track| {
+                track
+                    .iter()
+                    .find_map(|e| match e.kind {
+                        midly::TrackEventKind::Midi { channel, .. } => Some(channel.as_int()),
+                        _ => None,
+                    })
+                    .unwrap()
+            })
+            .collect();
+        let expected: Vec<u8> = (0..16).filter(|&c| c != DRUM_CHANNEL).chain([0, 1]).collect();
+        assert_eq!(channels, expected);
+    }
+}
@@ -0,0 +1,137 @@
+// 리듬 파트: 드럼 음(킥·스네어·하이햇)을 단음 보이스 하나로 옮긴다.
+// 합주에서 남는 한 명이 리듬을 치도록, 역할마다 정한 음높이 하나로 적는다.
+
+use serde::{Deserialize, Serialize};
+
+use crate::converter::{Note, TPB};
+
+// 리듬 파트 음의 최대 길이 (4분음표). 다음 타격까지 늘리되 빈 마디를 끌고 가지 않게 한다.
+const MAX_HIT_TICKS: u32 = TPB;
+
+/// 리듬 파트에서 드럼 역할마다 칠 음높이 (MIDI 노트 번호)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PercussionPitches {
+    pub kick: u8,
+    pub snare: u8,
+    pub hat: u8,
+}
+
+impl Default for PercussionPitches {
+    fn default() -> Self {
+        PercussionPitches {
+            kick: 48,  // C3
+            snare: 55, // G3
+            hat: 60,   // C4
+        }
+    }
+}
+
+/// 드럼 음의 역할. 같은 때 여러 개를 치면 앞쪽(킥)을 남긴다.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum DrumRole {
+    Kick,
+    Snare,
+    Hat,
+}
+
+impl DrumRole {
+    /// GM 드럼 키의 역할. 탐·효과음 등 나머지는 리듬 파트에 넣지 않는다.
+    pub fn of(key: u8) -> Option<DrumRole> {
+        match key {
+            35 | 36 => Some(DrumRole::Kick),
+            37..=40 => Some(DrumRole::Snare), // 사이드 스틱·스네어·박수·일렉 스네어
+            42 | 44 | 46 | 49 | 51..=53 | 55 | 57 | 59 => Some(DrumRole::Hat), // 하이햇·심벌
+            _ => None,
+        }
+    }
+
+    fn pitch(self, pitches: &PercussionPitches) -> u8 {
+        match self {
+            DrumRole::Kick => pitches.kick,
+            DrumRole::Snare => pitches.snare,
+            DrumRole::Hat => pitches.hat,
+        }
+    }
+}
+
+/// 드럼 음들을 리듬 보이스 하나로 만든다. 같은 때 친 음은 킥 > 스네어 > 하이햇 하나만 남기고,
+/// 길이는 다음 타격까지 (최대 4분음표).
+/// 리듬 보이스와 함께, 담지 못한 원래 드럼 음(같은 때 친 나머지 음, 탐·효과음)을 돌려준다.
+pub fn rhythm_voice(drums: &[Note], pitches: &PercussionPitches) -> (Vec<Note>, Vec<Note>) {
+    let mut left_out = Vec::new();
+    let mut hits: Vec<(&Note, DrumRole)> = Vec::with_capacity(drums.len());
+    for n in drums {
+        match DrumRole::of(n.note) {
+            Some(role) => hits.push((n, role)),
+            None => left_out.push(n.clone()),
+        }
+    }
+    hits.sort_by_key(|&(n, role)| (n.start, role));
+    let mut kept: Vec<(&Note, DrumRole)> = Vec::with_capacity(hits.len());
+    for hit in hits {
+        match kept.last() {
+            Some(&(prev, _)) if prev.start == hit.0.start => left_out.push(hit.0.clone()),
+            _ => kept.push(hit),
+        }
+    }
+    let hits = kept;
+
+    let mut voice = Vec::with_capacity(hits.len());
+    for (i, &(n, role)) in hits.iter().enumerate() {
+        let next = hits.get(i + 1).map_or(u32::MAX, |&(m, _)| m.start);
+        let end = next.min(n.start + MAX_HIT_TICKS);
+        voice.push(Note {
+            note: role.pitch(pitches),
+            end,
+            duration: end - n.start,
+            ..n.clone()
+        });
+    }
+    (voice, left_out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hit(key: u8, start: u32) -> Note {
+        Note {
+            note: key,
+            start,
+            end: start + 24,
+            duration: 24,
+            velocity: 100,
+            volume: 127,
+            program: 0,
+            track: 0,
+            track_name: None,
+            channel: 9,
+            percussion: true,
+        }
+    }
+
+    // 킥+하이햇이 같이 나오면 킥, 스네어+하이햇이면 스네어. 탐은 빠지고 음은 다음 타격까지 늘어난다.
+    #[test]
+    fn simultaneous_hits_keep_highest_priority() {
+        let drums = vec![
+            hit(42, 0),
+            hit(36, 0),
+            hit(42, 192),
+            hit(38, 384),
+            hit(42, 384),
+            hit(45, 576), // 탐
+            hit(42, 768),
+        ];
+        let (voice, left_out) = rhythm_voice(&drums, &PercussionPitches::default());
+        let spans: Vec<(u8, u32, u32)> = voice.iter().map(|n| (n.note, n.start, n.end)).collect();
+        assert_eq!(
+            spans,
+            vec![(48, 0, 192), (60, 192, 384), (55, 384, 768), (60, 768, 768 + TPB)]
+        );
+        assert!(voice.iter().all(|n| n.percussion));
+        let mut left: Vec<(u8, u32)> = left_out.iter().map(|n| (n.note, n.start)).collect();
+        left.sort_unstable();
+        assert_eq!(left, vec![(42, 0), (42, 384), (45, 576)]);
+    }
+}
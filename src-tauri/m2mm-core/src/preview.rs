@@ -0,0 +1,198 @@
+// MML 미리 듣기: 게임에 붙여넣지 않고도 결과를 들어볼 수 있게 MML 을 WAV 로 합성한다.
+// 악기 소리를 흉내 내지는 않고, GM 악기군(gm_family_name)마다 단순한 파형 하나를 쓴다.
+//
+// parse_mml 로 읽으므로 T 변경·타이(&)는 게임과 같은 타이밍으로 반영된다.
+
+use serde::{Deserialize, Serialize};
+
+use crate::converter::{TempoChange, TPB};
+use crate::error::MmlParseError;
+use crate::mml_parser::parse_mml;
+use crate::utils::mml::gm_family_name;
+
+/// 미리 듣기 샘플레이트 (모노)
+pub const PREVIEW_SAMPLE_RATE: u32 = 22_050;
+
+// 음 하나의 최대 진폭. 6파트가 한꺼번에 울려도 크게 찌그러지지 않을 정도.
+const NOTE_GAIN: f32 = 0.2;
+// 음 앞뒤 페이드 (클릭 잡음 방지)
+const ATTACK_SECONDS: f32 = 0.005;
+const RELEASE_SECONDS: f32 = 0.03;
+// 뜯는·때리는 악기의 감쇠 시간 상수
+const PLUCK_DECAY_SECONDS: f32 = 0.6;
+
+/// 미리 듣기할 보이스 하나: MML 과 음색을 고를 GM 악기 번호
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PreviewVoice {
+    pub mml: String,
+    pub program: u8,
+}
+
+// 악기군별 파형
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Waveform {
+    Pluck,    // 감쇠하는 삼각파 (피아노·실로폰·기타)
+    Square,   // 사각파 (오르간·리드·파이프)
+    Triangle, // 삼각파 (베이스)
+    Saw,      // 톱니파 (현악·금관·신스)
+    Sine,     // 사인파 (그 밖)
+}
+
+fn waveform_for(program: u8) -> Waveform {
+    match gm_family_name(program) {
+        "피아노" | "실로폰" | "통기타" | "전통악기" => Waveform::Pluck,
+        "오르간" | "색소폰" | "플루트" => Waveform::Square,
+        "베이스" => Waveform::Triangle,
+        "현악" | "트럼펫" | "신스" => Waveform::Saw,
+        _ => Waveform::Sine,
+    }
+}
+
+// 위상(0~1)에서의 파형 값 (-1~1)
+fn oscillate(waveform: Waveform, phase: f32) -> f32 {
+    match waveform {
+        Waveform::Pluck | Waveform::Triangle => 1.0 - 4.0 * (phase - 0.5).abs(),
+        Waveform::Square => {
+            if phase < 0.5 {
+                0.6
+            } else {
+                -0.6
+            }
+        }
+        Waveform::Saw => 0.7 * (2.0 * phase - 1.0),
+        Waveform::Sine => (phase * std::f32::consts::TAU).sin(),
+    }
+}
+
+// 템포 맵으로 틱 → 초
+fn tick_to_seconds(tick: u32, tempo_changes: &[TempoChange]) -> f64 {
+    let mut seconds = 0.0;
+    let mut last_tick = 0;
+    let mut bpm = tempo_changes.first().map_or(120, |t| t.bpm);
+    for t in tempo_changes.iter().filter(|t| t.tick > 0 && t.tick < tick) {
+        seconds += (t.tick - last_tick) as f64 / TPB as f64 * 60.0 / bpm as f64;
+        last_tick = t.tick;
+        bpm = t.bpm;
+    }
+    seconds + (tick - last_tick) as f64 / TPB as f64 * 60.0 / bpm as f64
+}
+
+/// 보이스들을 한데 섞어 -1~1 샘플로 합성한다. 보이스마다 MML 속 T 변경을 따른다.
+pub fn render_samples(voices: &[PreviewVoice]) -> Result<Vec<f32>, MmlParseError> {
+    let rate = PREVIEW_SAMPLE_RATE as f32;
+    let mut samples: Vec<f32> = Vec::new();
+
+    for voice in voices {
+        let parsed = parse_mml(&voice.mml)?;
+        let waveform = waveform_for(voice.program);
+        for note in &parsed.notes {
+            let start = tick_to_seconds(note.start, &parsed.tempo_changes);
+            let end = tick_to_seconds(note.end, &parsed.tempo_changes);
+            let first = (start * rate as f64).round() as usize;
+            let len = (end * rate as f64).round() as usize - first;
+            if samples.len() < first + len {
+                samples.resize(first + len, 0.0);
+            }
+
+            let freq = 440.0 * 2f32.powf((note.note as f32 - 69.0) / 12.0);
+            let gain = NOTE_GAIN * note.velocity as f32 / 127.0;
+            let length = len as f32 / rate;
+            for (i, sample) in samples[first..first + len].iter_mut().enumerate() {
+                let t = i as f32 / rate;
+                let fade = (t / ATTACK_SECONDS).min((length - t) / RELEASE_SECONDS).min(1.0);
+                let decay = match waveform {
+                    Waveform::Pluck => (-t / PLUCK_DECAY_SECONDS).exp(),
+                    _ => 1.0,
+                };
+                *sample += oscillate(waveform, (t * freq).fract()) * gain * fade * decay;
+            }
+        }
+    }
+
+    // 파트가 많이 겹쳐 넘치는 곳이 있으면 전체를 같은 비율로 줄인다
+    let peak = samples.iter().fold(0.0f32, |m, s| m.max(s.abs()));
+    if peak > 1.0 {
+        samples.iter_mut().for_each(|s| *s /= peak);
+    }
+    Ok(samples)
+}
+
+/// -1~1 샘플을 16비트 모노 PCM WAV 파일 바이트로 만든다.
+pub fn encode_wav(samples: &[f32]) -> Vec<u8> {
+    let data_len = (samples.len() * 2) as u32;
+    let mut out = Vec::with_capacity(44 + data_len as usize);
+    out.extend_from_slice(b"RIFF");
+    out.extend_from_slice(&(36 + data_len).to_le_bytes());
+    out.extend_from_slice(b"WAVEfmt ");
+    out.extend_from_slice(&16u32.to_le_bytes()); // fmt 청크 크기
+    out.extend_from_slice(&1u16.to_le_bytes()); // PCM
+    out.extend_from_slice(&1u16.to_le_bytes()); // 모노
+    out.extend_from_slice(&PREVIEW_SAMPLE_RATE.to_le_bytes());
+    out.extend_from_slice(&(PREVIEW_SAMPLE_RATE * 2).to_le_bytes()); // 초당 바이트
+    out.extend_from_slice(&2u16.to_le_bytes()); // 샘플당 바이트
+    out.extend_from_slice(&16u16.to_le_bytes()); // 비트
+    out.extend_from_slice(b"data");
+    out.extend_from_slice(&data_len.to_le_bytes());
+    for s in samples {
+        out.extend_from_slice(&((s.clamp(-1.0, 1.0) * i16::MAX as f32) as i16).to_le_bytes());
+    }
+    out
+}
+
+/// 보이스들을 섞어 WAV 파일 바이트로 렌더링한다.
+pub fn render_wav(voices: &[PreviewVoice]) -> Result<Vec<u8>, MmlParseError> {
+    Ok(encode_wav(&render_samples(voices)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn voice(mml: &str, program: u8) -> PreviewVoice {
+        PreviewVoice {
+            mml: mml.to_string(),
+            program,
+        }
+    }
+
+    // 길이는 T 를 따른다: T120 4분음표 4개 = 2초, 중간에 T240 으로 바뀌면 뒤 절반은 두 배 빠르다.
+    #[test]
+    fn duration_follows_tempo_changes() {
+        let rate = PREVIEW_SAMPLE_RATE as usize;
+        let steady = render_samples(&[voice("T120O4L4CDEF", 0)]).unwrap();
+        assert_eq!(steady.len(), rate * 2);
+
+        let faster = render_samples(&[voice("T120O4L4CDT240EF", 0)]).unwrap();
+        assert_eq!(faster.len(), rate * 3 / 2);
+    }
+
+    // 타이로 이은 음은 끊기지 않고 한 음으로 울린다 (중간에 페이드로 인한 무음 구간이 없음).
+    #[test]
+    fn tied_notes_sound_as_one() {
+        let samples = render_samples(&[voice("T120V15O4L4C&C", 80)]).unwrap();
+        let rate = PREVIEW_SAMPLE_RATE as usize;
+        let around_tie = &samples[rate / 2 - 20..rate / 2 + 20];
+        assert!(around_tie.iter().any(|s| s.abs() > 0.1), "타이 지점에서 소리가 끊김");
+    }
+
+    // 여러 보이스를 섞어도 범위를 넘지 않고, WAV 헤더 크기가 샘플 수와 맞는다.
+    #[test]
+    fn mixed_voices_encode_to_valid_wav() {
+        let voices: Vec<PreviewVoice> =
+            (0..6).map(|i| voice("T120V15O4L8CEGCEGCEG", i * 16)).collect();
+        let samples = render_samples(&voices).unwrap();
+        assert!(samples.iter().all(|s| s.abs() <= 1.0));
+
+        let wav = encode_wav(&samples);
+        assert_eq!(&wav[0..4], b"RIFF");
+        assert_eq!(&wav[8..12], b"WAVE");
+        let data_len = u32::from_le_bytes(wav[40..44].try_into().unwrap()) as usize;
+        assert_eq!(data_len, samples.len() * 2);
+        assert_eq!(wav.len(), 44 + data_len);
+    }
+
+    #[test]
+    fn invalid_mml_is_reported() {
+        assert!(render_wav(&[voice("T120X", 0)]).is_err());
+    }
+}
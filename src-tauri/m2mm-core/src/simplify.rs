@@ -0,0 +1,250 @@
+// 보이스 단순화: 글자수 한도에 곡을 더 담으려고, 들었을 때 티가 덜 나는 음부터 덜어낸다.
+// 보이스 분배가 끝난 단음 보이스(음끼리 겹치지 않고 시작 순으로 정렬)를 받는다.
+
+use serde::{Deserialize, Serialize};
+
+use crate::converter::{Note, GRID_SIZE, TPB};
+
+// 꾸밈음으로 보는 가장 긴 음 (32분음표)
+const ORNAMENT_MAX_TICKS: u32 = TPB / 8;
+// 꾸밈음 바로 뒤의 본음은 꾸밈음보다 이만큼 이상 길어야 한다
+const ORNAMENT_RATIO: u32 = 4;
+// 빠른 반복음으로 보는 가장 긴 음 (16분음표)과, 반복음 사이에 허용하는 틈
+const REPEAT_MAX_TICKS: u32 = TPB / 4;
+const REPEAT_MAX_GAP: u32 = TPB / 16;
+// 단순화 사다리에서 빼는 짧은 음 (16분음표보다 짧은 음)
+const SHORT_NOTE_TICKS: u32 = TPB / 4;
+
+/// 단순화 단계. 앞 단계일수록 소리 차이가 작다.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Simplification {
+    /// 바로 뒤의 긴 음에 붙은 아주 짧은 꾸밈음을 빼고, 그 자리는 앞 음을 늘려 채운다
+    DropOrnaments,
+    /// 같은 음높이로 빠르게 반복되는 음을 한 음으로 합친다
+    MergeRepeats,
+    /// 이 길이(틱)보다 짧은 음을 빼고, 그 자리는 앞 음을 늘려 채운다
+    DropShortNotes(u32),
+    /// 시작·끝을 더 굵은 격자(틱)에 맞춘다
+    Quantize(u32),
+}
+
+/// 바쁜 보이스에 차례로 적용하는 단순화 단계
+pub const BALANCE_STEPS: [Simplification; 4] = [
+    Simplification::DropOrnaments,
+    Simplification::MergeRepeats,
+    Simplification::Quantize(TPB / 8),
+    Simplification::Quantize(TPB / 4),
+];
+
+/// 곡 전체를 한 칸에 담으려고 모든 보이스에 차례로 적용하는 단순화 사다리의 단계
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SimplifyLevel {
+    /// 모든 보이스를 더 굵은 격자(32분음표)에 맞춘다
+    CoarserGrid,
+    /// 16분음표보다 짧은 음을 뺀다
+    DropShortNotes,
+    /// 빠른 반복음을 한 음으로 합친다
+    MergeRepeats,
+    /// 가운데 화음 보이스를 뺀다 (멜로디·베이스는 남김)
+    DropInnerVoices,
+}
+
+/// 단순화 사다리. 곡이 한 칸에 들어갈 때까지 앞에서부터 쌓아 적용한다.
+pub const SIMPLIFY_LADDER: [SimplifyLevel; 4] = [
+    SimplifyLevel::CoarserGrid,
+    SimplifyLevel::DropShortNotes,
+    SimplifyLevel::MergeRepeats,
+    SimplifyLevel::DropInnerVoices,
+];
+
+impl SimplifyLevel {
+    /// 화면·명령줄에 보여 줄 이름
+    pub fn label(self) -> &'static str {
+        match self {
+            SimplifyLevel::CoarserGrid => "굵은 박자 격자",
+            SimplifyLevel::DropShortNotes => "짧은 음 빼기",
+            SimplifyLevel::MergeRepeats => "반복음 합치기",
+            SimplifyLevel::DropInnerVoices => "가운데 화음 빼기",
+        }
+    }
+
+    /// 보이스마다 적용하는 단순화 (보이스를 통째로 빼는 단계는 None)
+    pub fn voice_step(self) -> Option<Simplification> {
+        match self {
+            SimplifyLevel::CoarserGrid => Some(Simplification::Quantize(GRID_SIZE * 2)),
+            SimplifyLevel::DropShortNotes => Some(Simplification::DropShortNotes(SHORT_NOTE_TICKS)),
+            SimplifyLevel::MergeRepeats => Some(Simplification::MergeRepeats),
+            SimplifyLevel::DropInnerVoices => None,
+        }
+    }
+}
+
+/// 단음 보이스 하나에 단순화 단계 하나를 적용한다.
+pub fn simplify_voice(voice: &[Note], step: Simplification) -> Vec<Note> {
+    match step {
+        Simplification::DropOrnaments => drop_ornaments(voice),
+        Simplification::MergeRepeats => merge_repeats(voice),
+        Simplification::DropShortNotes(min_ticks) => drop_short_notes(voice, min_ticks),
+        Simplification::Quantize(grid) => quantize(voice, grid),
+    }
+}
+
+// 끝을 바꾼 음 (duration 도 맞춘다)
+fn with_end(n: &Note, end: u32) -> Note {
+    Note {
+        end,
+        duration: end - n.start,
+        ..n.clone()
+    }
+}
+
+fn drop_ornaments(voice: &[Note]) -> Vec<Note> {
+    let mut out: Vec<Note> = Vec::with_capacity(voice.len());
+    for (i, n) in voice.iter().enumerate() {
+        let ornament = n.duration <= ORNAMENT_MAX_TICKS
+            && voice
+                .get(i + 1)
+                .is_some_and(|m| m.start == n.end && m.duration >= n.duration * ORNAMENT_RATIO);
+        if !ornament {
+            out.push(n.clone());
+            continue;
+        }
+        // 앞 음에 바로 붙어 있었으면 앞 음을 늘리고, 아니면 쉼표로 둔다
+        if let Some(prev) = out.last_mut()
+            && prev.end == n.start
+        {
+            *prev = with_end(prev, n.end);
+        }
+    }
+    out
+}
+
+fn drop_short_notes(voice: &[Note], min_ticks: u32) -> Vec<Note> {
+    let mut out: Vec<Note> = Vec::with_capacity(voice.len());
+    for n in voice {
+        if n.duration >= min_ticks {
+            out.push(n.clone());
+        } else if let Some(prev) = out.last_mut()
+            && prev.end == n.start
+        {
+            *prev = with_end(prev, n.end);
+        }
+    }
+    out
+}
+
+fn merge_repeats(voice: &[Note]) -> Vec<Note> {
+    let mut out: Vec<Note> = Vec::with_capacity(voice.len());
+    for n in voice {
+        if let Some(prev) = out.last_mut()
+            && prev.note == n.note
+            && n.duration <= REPEAT_MAX_TICKS
+            && n.start.saturating_sub(prev.end) <= REPEAT_MAX_GAP
+        {
+            *prev = with_end(prev, n.end);
+            continue;
+        }
+        out.push(n.clone());
+    }
+    out
+}
+
+fn quantize(voice: &[Note], grid: u32) -> Vec<Note> {
+    let snap = |t: u32| (t + grid / 2) / grid * grid;
+    let mut out: Vec<Note> = Vec::with_capacity(voice.len());
+    let mut prev_duration = 0; // 마지막으로 남긴 음의 원래 길이
+    for n in voice {
+        let start = snap(n.start);
+        let end = snap(n.end).max(start + grid);
+        if let Some(prev) = out.last_mut()
+            && start < prev.end
+        {
+            if start <= prev.start {
+                // 같은 격자로 모인 음은 원래 더 길었던 음 하나만 남긴다
+                if n.duration > prev_duration {
+                    out.pop();
+                } else {
+                    continue;
+                }
+            } else {
+                *prev = with_end(prev, start);
+            }
+        }
+        prev_duration = n.duration;
+        out.push(Note {
+            start,
+            end,
+            duration: end - start,
+            ..n.clone()
+        });
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn note(num: u8, start: u32, duration: u32) -> Note {
+        Note {
+            note: num,
+            start,
+            end: start + duration,
+            duration,
+            velocity: 100,
+            volume: 127,
+            program: 0,
+            track: 0,
+            track_name: None,
+            channel: 0,
+            percussion: false,
+        }
+    }
+
+    fn spans(voice: &[Note]) -> Vec<(u8, u32, u32)> {
+        voice.iter().map(|n| (n.note, n.start, n.end)).collect()
+    }
+
+    // 본음 앞 짧은 꾸밈음은 빠지고 앞 음이 그 자리를 채운다. 빠른 음계(본음이 길지 않음)는 그대로.
+    #[test]
+    fn ornaments_are_dropped_into_previous_note() {
+        let voice = vec![note(60, 0, 336), note(62, 336, 48), note(64, 384, 384)];
+        let simplified = simplify_voice(&voice, Simplification::DropOrnaments);
+        assert_eq!(spans(&simplified), vec![(60, 0, 384), (64, 384, 768)]);
+
+        let run: Vec<Note> = (0..8).map(|i| note(60 + i as u8, i * 48, 48)).collect();
+        assert_eq!(simplify_voice(&run, Simplification::DropOrnaments).len(), 8);
+    }
+
+    // 짧은 음은 빠지고, 앞 음에 붙어 있었으면 앞 음이 늘어난다. 맨 앞 짧은 음은 쉼표가 된다.
+    #[test]
+    fn short_notes_are_dropped() {
+        let voice = vec![note(60, 0, 48), note(62, 48, 384), note(64, 432, 48), note(65, 480, 96)];
+        let simplified = simplify_voice(&voice, Simplification::DropShortNotes(96));
+        assert_eq!(spans(&simplified), vec![(62, 48, 480), (65, 480, 576)]);
+    }
+
+    // 같은 음 16분음표 연타는 한 음으로, 다른 음이나 긴 음은 합치지 않는다.
+    #[test]
+    fn fast_repeats_merge() {
+        let mut voice: Vec<Note> = (0..4).map(|i| note(60, i * 96, 96)).collect();
+        voice.push(note(62, 384, 96));
+        voice.push(note(62, 480, 384));
+        let simplified = simplify_voice(&voice, Simplification::MergeRepeats);
+        assert_eq!(spans(&simplified), vec![(60, 0, 384), (62, 384, 480), (62, 480, 864)]);
+    }
+
+    // 격자에 맞추면 겹치지 않게 앞 음 꼬리를 자르고, 같은 칸으로 모인 음은 가장 긴 음만 남긴다.
+    #[test]
+    fn quantize_keeps_voice_monophonic() {
+        let voice = vec![note(60, 0, 100), note(62, 100, 10), note(64, 110, 270)];
+        let simplified = simplify_voice(&voice, Simplification::Quantize(96));
+        assert_eq!(spans(&simplified), vec![(60, 0, 96), (64, 96, 384)]);
+
+        let crowded = vec![note(60, 0, 20), note(62, 20, 20), note(64, 40, 56)];
+        let simplified = simplify_voice(&crowded, Simplification::Quantize(96));
+        assert_eq!(spans(&simplified), vec![(64, 0, 96)]);
+    }
+}